@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::fmt;
+use crate::day10::vm::{Instruction, OPS};
+
+// binary programs start with this, followed by one opcode byte per instruction and,
+// for instructions with an operand, the operand as a zigzag LEB128 varint
+pub const MAGIC: &[u8; 4] = b"D10\x01";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    BadMagic,
+    UnknownOpcode { offset: usize, opcode: u8 },
+    Truncated { offset: usize },
+    OperandOverflow { offset: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::BadMagic => write!(f, "not a day10 program, bad magic bytes"),
+            DecodeError::UnknownOpcode { offset, opcode } => write!(f, "unknown opcode {} at byte {}", opcode, offset),
+            DecodeError::Truncated { offset } => write!(f, "program ends in the middle of the instruction at byte {}", offset),
+            DecodeError::OperandOverflow { offset } => write!(f, "operand at byte {} doesn't fit in 64 bits", offset),
+        }
+    }
+}
+
+// strips a `;` comment and splits off a leading `label:`
+fn split_line(line: &str) -> (Option<&str>, &str) {
+    let code = line.split(';').next().unwrap().trim();
+    match code.split_once(':') {
+        Some((label, rest)) => (Some(label.trim()), rest.trim()),
+        None => (None, code),
+    }
+}
+
+fn is_name(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// assembles source with `;` comments, `name:` labels and `.const NAME value` constants.
+// operands can be numbers, constants, or for jumps a label to jump to
+pub fn assemble(source: &[String]) -> Result<Vec<Instruction>, AsmError> {
+    let error = |line: usize, message: String| AsmError { line: line + 1, message };
+
+    // first pass collects where every label and constant points
+    let mut labels = HashMap::<&str, usize>::new();
+    let mut constants = HashMap::<&str, i64>::new();
+    let mut statements = vec![];
+
+    for (i, line) in source.iter().enumerate() {
+        let (label, code) = split_line(line);
+
+        if let Some(label) = label {
+            if !is_name(label) {
+                return Err(error(i, format!("bad label {:?}", label)))
+            }
+            if labels.insert(label, statements.len()).is_some() {
+                return Err(error(i, format!("label {} defined twice", label)))
+            }
+        }
+
+        if let Some(definition) = code.strip_prefix(".const") {
+            let parts: Vec<&str> = definition.split_whitespace().collect();
+            match parts.as_slice() {
+                [name, value] if is_name(name) => {
+                    let value = value.parse::<i64>().map_err(|_| error(i, format!("bad constant value {:?}", value)))?;
+                    if constants.insert(name, value).is_some() {
+                        return Err(error(i, format!("constant {} defined twice", name)))
+                    }
+                },
+                _ => return Err(error(i, "expected .const NAME value".to_string())),
+            }
+        } else if !code.is_empty() {
+            statements.push((i, code));
+        }
+    }
+
+    statements.into_iter()
+        .enumerate()
+        .map(|(pc, (i, code))| {
+            let parts: Vec<&str> = code.split_whitespace().collect();
+            let op = OPS.iter()
+                .position(|def| def.name == parts[0])
+                .ok_or(error(i, format!("unknown instruction {:?}", parts[0])))?;
+            let def = &OPS[op];
+
+            if parts.len() - 1 != def.arity {
+                return Err(error(i, format!("{} takes {} operand(s)", def.name, def.arity)))
+            }
+
+            let operand = match parts.get(1) {
+                None => 0,
+                Some(arg) => {
+                    if let Ok(value) = arg.parse::<i64>() {
+                        value
+                    } else if let Some(value) = constants.get(arg) {
+                        *value
+                    } else if let Some(target) = labels.get(arg).filter(|_| def.relative) {
+                        *target as i64 - pc as i64
+                    } else if labels.contains_key(arg) {
+                        return Err(error(i, format!("{} can't take a label", def.name)))
+                    } else {
+                        return Err(error(i, format!("unknown symbol {:?}", arg)))
+                    }
+                },
+            };
+
+            Ok(Instruction { op, operand })
+        })
+        .collect()
+}
+
+// one instruction per line, the same text `parse_program` reads
+pub fn disassemble(program: &[Instruction]) -> Vec<String> {
+    program.iter().map(|i| i.to_string()).collect()
+}
+
+pub fn encode(program: &[Instruction]) -> Vec<u8> {
+    let mut res = MAGIC.to_vec();
+
+    for instruction in program {
+        res.push(instruction.op as u8);
+        if instruction.def().arity > 0 {
+            let mut zigzag = ((instruction.operand << 1) ^ (instruction.operand >> 63)) as u64;
+            loop {
+                let byte = (zigzag & 0x7f) as u8;
+                zigzag >>= 7;
+                if zigzag == 0 {
+                    res.push(byte);
+                    break
+                }
+                res.push(byte | 0x80);
+            }
+        }
+    }
+
+    res
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Vec<Instruction>, DecodeError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(DecodeError::BadMagic)
+    }
+
+    let mut res = vec![];
+    let mut i = MAGIC.len();
+    while i < bytes.len() {
+        let offset = i;
+        let op = bytes[i] as usize;
+        i += 1;
+
+        let def = OPS.get(op).ok_or(DecodeError::UnknownOpcode { offset, opcode: bytes[offset] })?;
+        let mut zigzag: u64 = 0;
+        if def.arity > 0 {
+            let mut shift = 0;
+            loop {
+                let byte = *bytes.get(i).ok_or(DecodeError::Truncated { offset })?;
+                i += 1;
+                if shift > 63 || (shift == 63 && byte & 0x7e != 0) {
+                    return Err(DecodeError::OperandOverflow { offset })
+                }
+                zigzag |= ((byte & 0x7f) as u64) << shift;
+                shift += 7;
+                if byte & 0x80 == 0 {
+                    break
+                }
+            }
+        }
+
+        let operand = ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64);
+        res.push(Instruction { op, operand });
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lcg::Lcg;
+    use crate::day10::asm::{assemble, decode, disassemble, encode, AsmError, DecodeError, MAGIC};
+    use crate::day10::vm::{parse_program, Instruction, Vm, MAX_CYCLES, OPS, X};
+
+    fn source(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    #[test]
+    fn test_assemble() {
+        let program = assemble(&source(&[
+            "; doubles x three times",
+            ".const TIMES 3",
+            "        addy TIMES",
+            "again:  mulx 2      ; x *= 2",
+            "        addy -1",
+            "        jnz again",
+            "",
+            "done:",
+            "        jmp end",
+            "        addx 100",
+            "end:    noop",
+        ])).unwrap();
+
+        assert_eq!(disassemble(&program), source(&["addy 3", "mulx 2", "addy -1", "jnz -2", "jmp 2", "addx 100", "noop"]));
+
+        let mut vm = Vm::new();
+        vm.run(&program, MAX_CYCLES).unwrap();
+        assert_eq!(vm.registers[X], 8);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let err = |lines: &[&str]| assemble(&source(lines)).unwrap_err();
+
+        assert_eq!(err(&["noop", "subx 1"]), AsmError { line: 2, message: "unknown instruction \"subx\"".to_string() });
+        assert_eq!(err(&["a: noop", "a: noop"]).message, "label a defined twice");
+        assert_eq!(err(&["addx SOMETHING"]).message, "unknown symbol \"SOMETHING\"");
+        assert_eq!(err(&["a: addx a"]).message, "addx can't take a label");
+        assert_eq!(err(&["noop 1"]).message, "noop takes 0 operand(s)");
+        assert_eq!(err(&[".const A"]).message, "expected .const NAME value");
+        assert_eq!(err(&["1a: noop"]).message, "bad label \"1a\"");
+        assert_eq!(err(&["noop", "addx 1", "addx"]).to_string(), "line 3: addx takes 1 operand(s)");
+    }
+
+    #[test]
+    fn test_encode() {
+        let program = parse_program(source(&["noop", "addx 3", "addx -5", "addx 64"])).unwrap();
+        let bytes = encode(&program);
+        assert_eq!(&bytes[..4], MAGIC);
+        assert_eq!(&bytes[4..], &[0, 1, 6, 1, 9, 1, 0x80, 0x01]);
+        assert_eq!(decode(&bytes), Ok(program));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(decode(b"nope"), Err(DecodeError::BadMagic));
+        assert_eq!(decode(b"D10\x01\x00\x63"), Err(DecodeError::UnknownOpcode { offset: 5, opcode: 0x63 }));
+        assert_eq!(decode(b"D10\x01\x01\x80"), Err(DecodeError::Truncated { offset: 4 }));
+        let mut too_long = MAGIC.to_vec();
+        too_long.push(1);
+        too_long.extend([0xff; 10]);
+        too_long.push(0x01);
+        assert_eq!(decode(&too_long), Err(DecodeError::OperandOverflow { offset: 4 }));
+    }
+
+    #[test]
+    fn test_round_trip_property() {
+        // pseudo random programs, deterministic so failures reproduce
+        let mut rng = Lcg::new(10);
+
+        for _ in 0..500 {
+            let len = rng.below(40);
+            let program: Vec<Instruction> = (0..len)
+                .map(|_| {
+                    let op = rng.below(OPS.len());
+                    let operand = match (OPS[op].arity, rng.below(4)) {
+                        (0, _) => 0,
+                        // mostly small numbers with the occasional extreme one
+                        (_, 0) => [i64::MIN, i64::MAX, -1, 0][rng.below(4)],
+                        (_, _) => rng.below(2001) as i64 - 1000,
+                    };
+                    Instruction { op, operand }
+                })
+                .collect();
+
+            let bytes = encode(&program);
+            assert_eq!(decode(&bytes), Ok(program.clone()));
+
+            let text = disassemble(&program);
+            assert_eq!(parse_program(text.clone()), Ok(program.clone()));
+            assert_eq!(assemble(&text), Ok(program.clone()));
+            assert_eq!(disassemble(&decode(&bytes).unwrap()), text);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use crate::day10::vm::{Instruction, Tick, Vm, MAX_CYCLES, REGISTERS, X};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    // stop before this cycle runs
+    Cycle(usize),
+    // stop before the instruction at this index starts
+    Pc(usize),
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Breakpoint::Cycle(cycle) => write!(f, "cycle {}", cycle),
+            Breakpoint::Pc(pc) => write!(f, "pc {}", pc),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(Breakpoint),
+    Halted,
+    // ran max_cycles without reaching either
+    CycleLimit,
+}
+
+pub fn trace_header() -> &'static str {
+    "cycle,pc,instruction,x,pixel"
+}
+
+pub fn trace_line(tick: &Tick) -> String {
+    format!("{},{},{},{},{}", tick.cycle, tick.pc, tick.instruction, tick.registers[X], tick.pixel())
+}
+
+pub struct Debugger<'p> {
+    program: &'p [Instruction],
+    vm: Vm<'static>,
+    pub breakpoints: Vec<Breakpoint>,
+    // the most cycles a single continue runs
+    pub max_cycles: usize,
+    // every cycle run so far
+    pub history: Vec<Tick>,
+}
+
+impl<'p> Debugger<'p> {
+    pub fn new(program: &'p [Instruction]) -> Debugger<'p> {
+        Debugger {
+            program,
+            vm: Vm::new(),
+            breakpoints: vec![],
+            max_cycles: MAX_CYCLES,
+            history: vec![],
+        }
+    }
+
+    fn hit(&self) -> Option<Breakpoint> {
+        let next = self.vm.peek(self.program)?;
+        self.breakpoints.iter()
+            .find(|bp| match bp {
+                Breakpoint::Cycle(cycle) => next.cycle == *cycle,
+                Breakpoint::Pc(pc) => next.pc == *pc && self.vm.at_instruction_start(),
+            })
+            .cloned()
+    }
+
+    // runs one cycle
+    pub fn step(&mut self) -> Option<Tick> {
+        let tick = self.vm.step(self.program)?;
+        self.history.push(tick.clone());
+        Some(tick)
+    }
+
+    // runs cycles until the current instruction has finished
+    pub fn next(&mut self) -> Vec<Tick> {
+        let mut res = vec![];
+        while let Some(tick) = self.step() {
+            res.push(tick);
+            if self.vm.at_instruction_start() {
+                break
+            }
+        }
+        res
+    }
+
+    // runs until the next breakpoint, always making at least one cycle of progress
+    pub fn resume(&mut self) -> Stop {
+        if self.step().is_none() {
+            return Stop::Halted
+        }
+        for _ in 1..self.max_cycles {
+            if let Some(bp) = self.hit() {
+                return Stop::Breakpoint(bp)
+            }
+            if self.step().is_none() {
+                return Stop::Halted
+            }
+        }
+        Stop::CycleLimit
+    }
+
+    pub fn inspect(&self) -> String {
+        let registers = REGISTERS.iter()
+            .zip(self.vm.registers.iter())
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        match self.vm.peek(self.program) {
+            Some(next) => format!(
+                "cycle {} pc {} ({}) {} beam column {} pixel {}",
+                next.cycle, next.pc, next.instruction, registers, next.column(), next.pixel(),
+            ),
+            None => format!("halted after cycle {} {}", self.vm.cycle - 1, registers),
+        }
+    }
+
+    pub fn trace(&self) -> String {
+        std::iter::once(trace_header().to_string())
+            .chain(self.history.iter().map(trace_line))
+            .map(|line| line + "\n")
+            .collect()
+    }
+
+    fn command(&mut self, words: &[&str], out: &mut impl Write) -> io::Result<bool> {
+        let number = |i: usize| words.get(i).and_then(|w| w.parse::<usize>().ok());
+
+        match words {
+            ["s"] | ["step"] | ["s", _] | ["step", _] => {
+                for _ in 0..number(1).unwrap_or(1) {
+                    match self.step() {
+                        Some(tick) => writeln!(out, "{}", trace_line(&tick))?,
+                        None => break,
+                    }
+                }
+                writeln!(out, "{}", self.inspect())?;
+            },
+            ["n"] | ["next"] => {
+                for tick in self.next() {
+                    writeln!(out, "{}", trace_line(&tick))?;
+                }
+                writeln!(out, "{}", self.inspect())?;
+            },
+            ["c"] | ["continue"] => {
+                match self.resume() {
+                    Stop::Breakpoint(bp) => writeln!(out, "hit breakpoint at {}", bp)?,
+                    Stop::Halted => writeln!(out, "program finished")?,
+                    Stop::CycleLimit => writeln!(out, "still running after {} cycles", self.max_cycles)?,
+                }
+                writeln!(out, "{}", self.inspect())?;
+            },
+            ["b", kind, _] | ["break", kind, _] => {
+                let bp = match (*kind, number(2)) {
+                    ("cycle", Some(n)) => Breakpoint::Cycle(n),
+                    ("pc", Some(n)) => Breakpoint::Pc(n),
+                    _ => {
+                        writeln!(out, "usage: break cycle|pc <n>")?;
+                        return Ok(true)
+                    },
+                };
+                self.breakpoints.push(bp);
+                writeln!(out, "breakpoint {} at {}", self.breakpoints.len() - 1, bp)?;
+            },
+            ["d", _] | ["delete", _] => {
+                match number(1).filter(|i| *i < self.breakpoints.len()) {
+                    Some(i) => writeln!(out, "deleted breakpoint at {}", self.breakpoints.remove(i))?,
+                    None => writeln!(out, "no such breakpoint")?,
+                }
+            },
+            ["bl"] | ["breakpoints"] => {
+                for (i, bp) in self.breakpoints.iter().enumerate() {
+                    writeln!(out, "{}: {}", i, bp)?;
+                }
+            },
+            ["i"] | ["info"] => writeln!(out, "{}", self.inspect())?,
+            ["t", path] | ["trace", path] => {
+                fs::write(path, self.trace())?;
+                writeln!(out, "wrote {} cycles to {}", self.history.len(), path)?;
+            },
+            ["q"] | ["quit"] => return Ok(false),
+            [] => {},
+            _ => writeln!(out, "commands: step [n], next, continue, break cycle|pc <n>, delete <i>, breakpoints, info, trace <file>, quit")?,
+        }
+
+        Ok(true)
+    }
+
+    // reads commands a line at a time until `quit` or the input runs out
+    pub fn repl(&mut self, input: impl BufRead, mut out: impl Write) -> io::Result<()> {
+        writeln!(out, "{}", self.inspect())?;
+        for line in input.lines() {
+            let line = line?;
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if !self.command(&words, &mut out)? {
+                break
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use crate::day10::debugger::{Breakpoint, Debugger, Stop};
+    use crate::day10::vm::{parse_program, Instruction};
+
+    fn program() -> Vec<Instruction> {
+        parse_program(vec!["noop".to_string(), "addx 3".to_string(), "addx -5".to_string()]).unwrap()
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let program = program();
+        let mut debugger = Debugger::new(&program);
+        debugger.breakpoints = vec![Breakpoint::Cycle(3), Breakpoint::Pc(2)];
+
+        assert_eq!(debugger.resume(), Stop::Breakpoint(Breakpoint::Cycle(3)));
+        assert_eq!(debugger.inspect(), "cycle 3 pc 1 (addx 3) x=1 y=0 beam column 2 pixel #");
+        assert_eq!(debugger.resume(), Stop::Breakpoint(Breakpoint::Pc(2)));
+        assert_eq!(debugger.inspect(), "cycle 4 pc 2 (addx -5) x=4 y=0 beam column 3 pixel #");
+        assert_eq!(debugger.resume(), Stop::Halted);
+        assert_eq!(debugger.inspect(), "halted after cycle 5 x=-1 y=0");
+        assert_eq!(debugger.history.len(), 5);
+    }
+
+    #[test]
+    fn test_cycle_limit() {
+        let program = parse_program(vec!["jmp 0".to_string()]).unwrap();
+        let mut debugger = Debugger::new(&program);
+        debugger.max_cycles = 100;
+
+        assert_eq!(debugger.resume(), Stop::CycleLimit);
+        assert_eq!(debugger.history.len(), 100);
+        assert_eq!(debugger.resume(), Stop::CycleLimit);
+        assert_eq!(debugger.history.len(), 200);
+    }
+
+    #[test]
+    fn test_step_and_next() {
+        let program = program();
+        let mut debugger = Debugger::new(&program);
+
+        assert_eq!(debugger.step().map(|t| t.cycle), Some(1));
+        assert_eq!(debugger.step().map(|t| (t.cycle, t.pc)), Some((2, 1)));
+        // finishes the addx that's already half done
+        assert_eq!(debugger.next().len(), 1);
+        assert_eq!(debugger.next().len(), 2);
+        assert_eq!(debugger.step(), None);
+    }
+
+    #[test]
+    fn test_trace() {
+        let program = program();
+        let mut debugger = Debugger::new(&program);
+        debugger.resume();
+        assert_eq!(debugger.trace(), [
+            "cycle,pc,instruction,x,pixel",
+            "1,0,noop,1,#",
+            "2,1,addx 3,1,#",
+            "3,1,addx 3,1,#",
+            "4,2,addx -5,4,#",
+            "5,2,addx -5,4,#",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_repl() {
+        let program = program();
+        let mut debugger = Debugger::new(&program);
+        let input = Cursor::new("break pc 2\ncontinue\nstep\nbreakpoints\nnope\nquit\nstep\n");
+        let mut out = vec![];
+        debugger.repl(input, &mut out).unwrap();
+
+        assert_eq!(String::from_utf8(out).unwrap(), [
+            "cycle 1 pc 0 (noop) x=1 y=0 beam column 0 pixel #",
+            "breakpoint 0 at pc 2",
+            "hit breakpoint at pc 2",
+            "cycle 4 pc 2 (addx -5) x=4 y=0 beam column 3 pixel #",
+            "4,2,addx -5,4,#",
+            "cycle 5 pc 2 (addx -5) x=4 y=0 beam column 4 pixel #",
+            "0: pc 2",
+            "commands: step [n], next, continue, break cycle|pc <n>, delete <i>, breakpoints, info, trace <file>, quit",
+            "",
+        ].join("\n"));
+    }
+}
@@ -1 +1,5 @@
-pub mod day10;
\ No newline at end of file
+pub mod asm;
+pub mod day10;
+pub mod debugger;
+pub mod ocr;
+pub mod vm;
\ No newline at end of file
@@ -0,0 +1,145 @@
+use std::fmt;
+
+const GLYPH_HEIGHT: usize = 6;
+// letters sit in 5 pixel wide cells. all but Y are 4 pixels wide, the last column left
+// dark, and are padded out to the full cell when matching
+const CELL_WIDTH: usize = 5;
+
+const FONT: [(char, [&str; GLYPH_HEIGHT]); 18] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Y', ["#...#", "#...#", ".#.#.", "..#..", "..#..", "..#.."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OcrError {
+    BadHeight(usize),
+    RaggedRow { row: usize, width: usize, expected: usize },
+    UnknownGlyph { index: usize, glyph: Vec<String> },
+}
+
+impl fmt::Display for OcrError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OcrError::BadHeight(height) => {
+                write!(f, "expected {} rows of pixels, got {}", GLYPH_HEIGHT, height)
+            },
+            OcrError::RaggedRow { row, width, expected } => {
+                write!(f, "row {} is {} pixels wide, expected {}", row, width, expected)
+            },
+            OcrError::UnknownGlyph { index, glyph } => {
+                writeln!(f, "letter {} doesn't match any known glyph:", index)?;
+                write!(f, "{}", glyph.join("\n"))
+            },
+        }
+    }
+}
+
+// reads the capital letters off a CRT drawn with `#` for lit pixels
+pub fn decode(rows: &[String]) -> Result<String, OcrError> {
+    if rows.len() != GLYPH_HEIGHT {
+        return Err(OcrError::BadHeight(rows.len()))
+    }
+
+    let width = rows[0].chars().count();
+    for (row, line) in rows.iter().enumerate() {
+        let line_width = line.chars().count();
+        if line_width != width {
+            return Err(OcrError::RaggedRow { row, width: line_width, expected: width })
+        }
+    }
+
+    // the final cell may drop its blank column
+    let letters = width.div_ceil(CELL_WIDTH);
+
+    (0..letters)
+        .map(|index| {
+            let glyph: Vec<String> = rows.iter()
+                .map(|line| {
+                    let pixels: String = line.chars()
+                        .skip(index * CELL_WIDTH)
+                        .take(CELL_WIDTH)
+                        .map(|c| if c == '#' { '#' } else { '.' })
+                        .collect();
+                    format!("{:.<width$}", pixels, width = CELL_WIDTH)
+                })
+                .collect();
+
+            FONT.iter()
+                .find(|(_, pixels)| {
+                    pixels.iter().zip(glyph.iter()).all(|(a, b)| format!("{:.<width$}", a, width = CELL_WIDTH) == *b)
+                })
+                .map(|(letter, _)| *letter)
+                .ok_or(OcrError::UnknownGlyph { index, glyph })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::day10::ocr::{decode, OcrError};
+
+    fn screen(rows: &[&str]) -> Vec<String> {
+        rows.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn test_decode() {
+        let rows = screen(&[
+            "###..####.#..#.#....###..#..#.####..##..",
+            "#..#....#.#..#.#....#..#.#..#.#....#..#.",
+            "#..#...#..####.#....#..#.#..#.###..#....",
+            "###...#...#..#.#....###..#..#.#....#.##.",
+            "#.#..#....#..#.#....#....#..#.#....#..#.",
+            "#..#.####.#..#.####.#.....##..####..###.",
+        ]);
+        assert_eq!(decode(&rows), Ok("RZHLPUEG".to_string()));
+
+        // no trailing blank column
+        let rows = screen(&["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]);
+        assert_eq!(decode(&rows), Ok("K".to_string()));
+
+        // Y is the one letter that fills its whole cell
+        let rows = screen(&[
+            ".###.#...#",
+            "..#..#...#",
+            "..#...#.#.",
+            "..#....#..",
+            "..#....#..",
+            ".###...#..",
+        ]);
+        assert_eq!(decode(&rows), Ok("IY".to_string()));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(decode(&screen(&["####"])), Err(OcrError::BadHeight(1)));
+
+        let rows = screen(&["####.", "#...", "###..", "#....", "#....", "####."]);
+        assert_eq!(decode(&rows), Err(OcrError::RaggedRow { row: 1, width: 4, expected: 5 }));
+
+        let rows = screen(&["####.", "####.", "####.", "####.", "####.", "####."]);
+        let err = decode(&rows).unwrap_err();
+        assert_eq!(err, OcrError::UnknownGlyph { index: 0, glyph: vec!["####.".to_string(); 6] });
+        assert!(err.to_string().starts_with("letter 0 doesn't match any known glyph:\n####."));
+
+        // letters missing from the font, like M, are reported rather than guessed
+        let rows = screen(&["#...#", "##.##", "#.#.#", "#...#", "#...#", "#...#"]);
+        assert!(matches!(decode(&rows), Err(OcrError::UnknownGlyph { index: 0, .. })));
+    }
+}
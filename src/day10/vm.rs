@@ -0,0 +1,279 @@
+use std::fmt;
+use std::str::FromStr;
+
+pub const REGISTERS: [&str; 2] = ["x", "y"];
+pub const X: usize = 0;
+pub const Y: usize = 1;
+// far more than any puzzle program needs, low enough that a program stuck in a loop
+// gives up quickly
+pub const MAX_CYCLES: usize = 1_000_000;
+
+pub type Registers = [i64; REGISTERS.len()];
+
+// what happens to the program counter once an instruction has finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Next,
+    Jump(i64),
+}
+
+pub struct OpDef {
+    pub name: &'static str,
+    pub arity: usize,
+    pub cycles: usize,
+    // the operand is an offset from this instruction, so assembly can name a label instead
+    pub relative: bool,
+    // applied at the end of the instruction's last cycle
+    pub exec: fn(&mut Registers, i64) -> Flow,
+}
+
+pub const OPS: [OpDef; 6] = [
+    OpDef { name: "noop", arity: 0, cycles: 1, relative: false, exec: |_, _| Flow::Next },
+    OpDef { name: "addx", arity: 1, cycles: 2, relative: false, exec: |r, v| { r[X] += v; Flow::Next } },
+    OpDef { name: "addy", arity: 1, cycles: 2, relative: false, exec: |r, v| { r[Y] += v; Flow::Next } },
+    OpDef { name: "mulx", arity: 1, cycles: 4, relative: false, exec: |r, v| { r[X] *= v; Flow::Next } },
+    // relative jumps, `jnz` only taken while y is not zero
+    OpDef { name: "jmp", arity: 1, cycles: 1, relative: true, exec: |_, v| Flow::Jump(v) },
+    OpDef { name: "jnz", arity: 1, cycles: 2, relative: true, exec: |r, v| if r[Y] != 0 { Flow::Jump(v) } else { Flow::Next } },
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Instruction {
+    // index into `OPS`
+    pub op: usize,
+    pub operand: i64,
+}
+
+impl Instruction {
+    pub fn def(&self) -> &'static OpDef {
+        &OPS[self.op]
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let name = parts.first().ok_or("empty instruction")?;
+        let op = OPS.iter()
+            .position(|def| def.name == *name)
+            .ok_or(format!("unknown instruction {:?}", name))?;
+
+        if parts.len() - 1 != OPS[op].arity {
+            return Err(format!("{} takes {} operand(s), got {:?}", name, OPS[op].arity, s))
+        }
+        let operand = match parts.get(1) {
+            Some(v) => v.parse::<i64>().map_err(|_| format!("bad operand {:?}", v))?,
+            None => 0,
+        };
+
+        Ok(Instruction { op, operand })
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.def().arity {
+            0 => write!(f, "{}", self.def().name),
+            _ => write!(f, "{} {}", self.def().name, self.operand),
+        }
+    }
+}
+
+// machine state during a single cycle, before the running instruction has taken effect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tick {
+    pub cycle: usize,
+    pub pc: usize,
+    pub instruction: Instruction,
+    pub registers: Registers,
+}
+
+impl Tick {
+    pub fn signal_strength(&self) -> i64 {
+        self.cycle as i64 * self.registers[X]
+    }
+
+    // the CRT beam draws column (cycle - 1) % 40 while the sprite covers x - 1 ..= x + 1
+    pub fn column(&self) -> usize {
+        (self.cycle - 1) % 40
+    }
+
+    pub fn pixel(&self) -> char {
+        if (self.registers[X] - self.column() as i64).abs() <= 1 {
+            '#'
+        } else {
+            '.'
+        }
+    }
+}
+
+type Hook<'a> = Box<dyn FnMut(&Tick) + 'a>;
+
+pub struct Vm<'a> {
+    pub registers: Registers,
+    pub pc: usize,
+    // the cycle about to run, counting from 1
+    pub cycle: usize,
+    // cycles already spent on the instruction at `pc`
+    elapsed: usize,
+    hooks: Vec<Hook<'a>>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new() -> Vm<'a> {
+        let mut registers = [0; REGISTERS.len()];
+        registers[X] = 1;
+
+        Vm {
+            registers,
+            pc: 0,
+            cycle: 1,
+            elapsed: 0,
+            hooks: vec![],
+        }
+    }
+
+    // called with every cycle the machine runs
+    pub fn on_cycle(&mut self, hook: impl FnMut(&Tick) + 'a) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    pub fn halted(&self, program: &[Instruction]) -> bool {
+        self.pc >= program.len()
+    }
+
+    // true between instructions, when the next cycle starts a new one
+    pub fn at_instruction_start(&self) -> bool {
+        self.elapsed == 0
+    }
+
+    // the state the next cycle will run with
+    pub fn peek(&self, program: &[Instruction]) -> Option<Tick> {
+        Some(Tick {
+            cycle: self.cycle,
+            pc: self.pc,
+            instruction: *program.get(self.pc)?,
+            registers: self.registers,
+        })
+    }
+
+    // runs a single cycle, finishing the current instruction if this was its last
+    pub fn step(&mut self, program: &[Instruction]) -> Option<Tick> {
+        let tick = self.peek(program)?;
+        let instruction = tick.instruction;
+        self.hooks.iter_mut().for_each(|hook| hook(&tick));
+
+        self.cycle += 1;
+        self.elapsed += 1;
+        if self.elapsed >= instruction.def().cycles {
+            self.elapsed = 0;
+            self.pc = match (instruction.def().exec)(&mut self.registers, instruction.operand) {
+                Flow::Next => self.pc + 1,
+                // jumping before the start halts just like running off the end
+                Flow::Jump(offset) => (self.pc as i64).checked_add(offset)
+                    .and_then(|pc| usize::try_from(pc).ok())
+                    .unwrap_or(usize::MAX),
+            };
+        }
+
+        Some(tick)
+    }
+
+    // runs to the end of the program, giving up after `max_cycles` in case it never gets there
+    pub fn run(&mut self, program: &[Instruction], max_cycles: usize) -> Result<(), String> {
+        for _ in 0..max_cycles {
+            if self.step(program).is_none() {
+                return Ok(())
+            }
+        }
+        match self.halted(program) {
+            true => Ok(()),
+            false => Err(format!("still running after {} cycles, at pc {}", max_cycles, self.pc)),
+        }
+    }
+}
+
+pub fn parse_program(lines: Vec<String>) -> Result<Vec<Instruction>, String> {
+    lines.into_iter()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(i, l)| l.parse::<Instruction>().map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::day10::vm::{parse_program, Instruction, Vm, MAX_CYCLES, X, Y};
+
+    fn program(lines: &[&str]) -> Vec<Instruction> {
+        parse_program(lines.iter().map(|l| l.to_string()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_cycle_timing() {
+        let program = program(&["noop", "addx 3", "addx -5"]);
+        let mut xs = vec![];
+        let mut vm = Vm::new();
+        vm.on_cycle(|tick| xs.push((tick.cycle, tick.pc, tick.registers[X])));
+        vm.run(&program, MAX_CYCLES).unwrap();
+
+        assert_eq!(vm.registers[X], -1);
+        assert_eq!(vm.cycle, 6);
+        drop(vm);
+        assert_eq!(xs, vec![(1, 0, 1), (2, 1, 1), (3, 1, 1), (4, 2, 4), (5, 2, 4)]);
+    }
+
+    #[test]
+    fn test_extended_ops() {
+        // x = 1 * 2 * 2 * 2 using y as a loop counter
+        let program = program(&["addy 3", "mulx 2", "addy -1", "jnz -2", "addx 1"]);
+        let mut cycles = 0;
+        let mut vm = Vm::new();
+        vm.on_cycle(|_| cycles += 1);
+        vm.run(&program, MAX_CYCLES).unwrap();
+
+        assert_eq!((vm.registers[X], vm.registers[Y]), (9, 0));
+        drop(vm);
+        assert_eq!(cycles, 2 + 3 * (4 + 2 + 2) + 2);
+    }
+
+    #[test]
+    fn test_jump_out_of_program() {
+        let program = program(&["jmp -5", "addx 1"]);
+        let mut vm = Vm::new();
+        vm.run(&program, MAX_CYCLES).unwrap();
+        assert!(vm.halted(&program));
+        assert_eq!(vm.registers[X], 1);
+    }
+
+    #[test]
+    fn test_cycle_limit() {
+        let program = program(&["jmp 0"]);
+        let mut cycles = 0;
+        let mut vm = Vm::new();
+        vm.on_cycle(|_| cycles += 1);
+        assert_eq!(vm.run(&program, 1000), Err("still running after 1000 cycles, at pc 0".to_string()));
+        drop(vm);
+        assert_eq!(cycles, 1000);
+
+        // a program finishing on exactly its last allowed cycle is fine
+        let program = self::program(&["noop", "addx 1"]);
+        assert_eq!(Vm::new().run(&program, 3), Ok(()));
+        assert!(Vm::new().run(&program, 2).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!("noop".parse::<Instruction>().map(|i| i.to_string()), Ok("noop".to_string()));
+        assert_eq!("addx  -4".parse::<Instruction>().map(|i| i.to_string()), Ok("addx -4".to_string()));
+        assert!("addx".parse::<Instruction>().is_err());
+        assert!("noop 3".parse::<Instruction>().is_err());
+        assert!("subx 3".parse::<Instruction>().is_err());
+        assert_eq!(
+            parse_program(vec!["noop".to_string(), "addx x".to_string()]),
+            Err("line 2: bad operand \"x\"".to_string())
+        );
+    }
+}
@@ -1,188 +1,223 @@
 use std::fmt::{Display, Formatter};
 use std::str::FromStr;
-use regex::Regex;
+use num_bigint::BigUint;
 
-#[derive(Debug, Clone)]
-struct Ring {
-    size: u32,
-    value: u32,
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
 }
 
-impl Ring {
-    fn add_const(&mut self, value: u32) {
-        self.value = (self.value + value) % self.size;
-    }
+// None once it no longer fits
+fn lcm(a: u64, b: u64) -> Option<u64> {
+    (a / gcd(a, b)).checked_mul(b)
+}
+
+// for when it doesn't. gcd(a, b) is gcd(a % b, b), which fits in a u64
+fn wide_lcm(a: &BigUint, b: u64) -> BigUint {
+    let rest = u64::try_from(a % b).unwrap();
+    a / gcd(b, rest) * b
+}
 
-    fn add_ring(&mut self, other: &Ring) {
-        if other.size != self.size {
-            panic!("cannot add rings of different sizes");
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Worry {
+    // residue modulo the LCM of every monkey's divisor, which answers all of their tests
+    // while staying small. only valid as long as nothing ever divides the worry
+    Modular { value: u64, modulus: u64 },
+    // the same, for when that lcm doesn't fit in a u64
+    WideModular { value: BigUint, modulus: BigUint },
+    // the real value, for when relief divides it
+    Exact(BigUint),
+}
+
+impl Worry {
+    fn new(value: u64, modulus: Option<u64>) -> Worry {
+        match modulus {
+            Some(modulus) => Worry::Modular { value: value % modulus, modulus },
+            None => Worry::Exact(BigUint::from(value)),
         }
-        self.add_const(other.value);
     }
 
-    fn mul_const(&mut self, value: u32) {
-        self.value = (self.value * value) % self.size;
+    // `modular` gets both residues and the modulus
+    fn combine(&self, other: &Worry, modular: fn(u128, u128, u128) -> u128, exact: fn(&BigUint, &BigUint) -> BigUint) -> Worry {
+        match (self, other) {
+            (Worry::Modular { value: a, modulus }, Worry::Modular { value: b, modulus: other_modulus }) => {
+                if modulus != other_modulus {
+                    panic!("cannot combine worries with different moduli");
+                }
+                let value = modular(*a as u128, *b as u128, *modulus as u128) % *modulus as u128;
+                Worry::Modular { value: value as u64, modulus: *modulus }
+            },
+            (Worry::WideModular { value: a, modulus }, Worry::WideModular { value: b, modulus: other_modulus }) => {
+                if modulus != other_modulus {
+                    panic!("cannot combine worries with different moduli");
+                }
+                // adding the modulus keeps the residue, and keeps a subtraction above zero
+                let value = exact(&(a + modulus), b) % modulus;
+                Worry::WideModular { value, modulus: modulus.clone() }
+            },
+            (Worry::Exact(a), Worry::Exact(b)) => Worry::Exact(exact(a, b)),
+            _ => panic!("cannot combine worries of different kinds"),
+        }
     }
 
-    fn mul_ring(&mut self, other: &Ring) {
-        if other.size != self.size {
-            panic!("cannot add rings of different sizes");
-        }
-        self.mul_const(other.value);
+    fn add(&self, other: &Worry) -> Worry {
+        self.combine(other, |a, b, _| a + b, |a, b| a + b)
     }
-}
 
-#[derive(Debug, Clone)]
-struct DivisorChecker {
-    rings: Vec<Ring>,
-}
+    fn mul(&self, other: &Worry) -> Worry {
+        self.combine(other, |a, b, _| a * b, |a, b| a * b)
+    }
 
-impl DivisorChecker {
-    fn new(initial: u32) -> DivisorChecker {
-        DivisorChecker {
-            rings: (1..30).map(|x| Ring{
-                size: x,
-                value: initial % x,
-            }).collect(),
+    // a modular worry keeps the residue of what may be a negative number, which answers
+    // divisibility just the same. exact worries get relieved, and the notes don't say
+    // how a negative worry rounds, so going below zero is an error there
+    fn sub(&self, other: &Worry) -> Result<Worry, String> {
+        match (self, other) {
+            (Worry::Exact(a), Worry::Exact(b)) if a < b => Err(format!("{} - {} is below zero", a, b)),
+            _ => Ok(self.combine(other, |a, b, modulus| a + modulus - b % modulus, |a, b| a - b)),
         }
     }
 
-    fn divisible_by(&self, value: u32) -> bool {
-        if value >= 30 {
-            panic!("cannot check divisible by for value >= 20");
+    fn div(&self, other: &Worry) -> Result<Worry, String> {
+        if other.is_zero() {
+            return Err(format!("{} divided by zero", self));
         }
+        match self {
+            Worry::Exact(_) => Ok(self.combine(other, |_, _, _| unreachable!(), |a, b| a / b)),
+            _ => panic!("cannot divide a modular worry"),
+        }
+    }
 
-
-        self.rings[value as usize - 1].value == 0
+    fn is_zero(&self) -> bool {
+        match self {
+            Worry::Modular { value, .. } => *value == 0,
+            Worry::WideModular { value, .. } | Worry::Exact(value) => *value == BigUint::from(0u32),
+        }
     }
 
-    fn add_const(mut self, value: u32) -> Self {
-        self.rings.iter_mut().for_each(|r| r.add_const(value));
-        self
+    // same representation as `self`, holding a plain number
+    fn constant(&self, value: u64) -> Worry {
+        match self {
+            Worry::Modular { modulus, .. } => Worry::new(value, Some(*modulus)),
+            Worry::WideModular { modulus, .. } => Worry::WideModular { value: value % modulus, modulus: modulus.clone() },
+            Worry::Exact(_) => Worry::new(value, None),
+        }
     }
 
-    fn add_divisor_checker(mut self, dc: &DivisorChecker) -> Self {
-        self.rings.iter_mut().enumerate().for_each(|(i, r)| r.add_ring(&dc.rings[i]));
-        self
+    fn relieve(self, factor: u64) -> Worry {
+        match self {
+            _ if factor == 1 => self,
+            Worry::Exact(value) => Worry::Exact(value / factor),
+            _ => panic!("cannot divide a modular worry"),
+        }
     }
 
-    fn mul_const(mut self, value: u32) -> Self {
-        self.rings.iter_mut().for_each(|r| r.mul_const(value));
-        self
+    fn divisible_by(&self, divisor: u64) -> bool {
+        match self {
+            Worry::Modular { value, modulus } => {
+                if modulus % divisor != 0 {
+                    panic!("modulus {} can't answer divisibility by {}", modulus, divisor);
+                }
+                value % divisor == 0
+            },
+            Worry::WideModular { value, modulus } => {
+                if modulus % divisor != BigUint::from(0u32) {
+                    panic!("modulus {} can't answer divisibility by {}", modulus, divisor);
+                }
+                value % divisor == BigUint::from(0u32)
+            },
+            Worry::Exact(value) => value % divisor == BigUint::from(0u32),
+        }
     }
+}
 
-    fn mul_divisor_checker(mut self, dc: &DivisorChecker) -> Self {
-        self.rings.iter_mut().enumerate().for_each(|(i, r)| r.mul_ring(&dc.rings[i]));
-        self
+impl Display for Worry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Worry::Modular { value, .. } => write!(f, "{}", value),
+            Worry::WideModular { value, .. } | Worry::Exact(value) => write!(f, "{}", value),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 enum Operand {
     Old,
-    Value(u32)
+    Value(u64)
 }
 
 impl FromStr for Operand {
-    type Err = ();
+    type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        s.parse::<u32>().map(|x| Operand::Value(x)).or_else(|_| Ok(Operand::Old))
+        match s {
+            "old" => Ok(Operand::Old),
+            _ => s.parse::<u64>().map(Operand::Value).map_err(|_| format!("expected old or a number, got {:?}", s)),
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Operation {
     Add,
+    Sub,
     Mul,
+    Div,
 }
 
 impl FromStr for Operation {
-    type Err = ();
+    type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "+" => Ok(Operation::Add),
+            "-" => Ok(Operation::Sub),
             "*" => Ok(Operation::Mul),
-            _ => Err(()),
+            "/" => Ok(Operation::Div),
+            _ => Err(format!("unknown operator {:?}", s)),
         }
     }
 }
 
-impl Operation {
-    fn apply(&self, old: &DivisorChecker, left: &Operand, right: &Operand) -> DivisorChecker {
+impl Operand {
+    fn value(&self, old: &Worry) -> Worry {
         match self {
-            Operation::Add => Operation::add(old, left, right),
-            Operation::Mul => Operation::mul(old, left, right),
-        }
-    }
-
-    fn add(old: &DivisorChecker, left: &Operand, right: &Operand) -> DivisorChecker {
-        match left {
-            Operand::Old => {
-                match right {
-                    Operand::Old => {
-                        old.clone().add_divisor_checker(&old)
-                    },
-                    Operand::Value(value) => {
-                        old.clone().add_const(value.to_owned())
-                    }
-                }
-            }
-            Operand::Value(value) => {
-                match right {
-                    Operand::Old => {
-                        old.clone().add_const(value.to_owned())
-                    },
-                    Operand::Value(right) => DivisorChecker::new(value * right)
-                }
-            }
+            Operand::Old => old.clone(),
+            Operand::Value(value) => old.constant(*value),
         }
     }
+}
 
-    fn mul(old: &DivisorChecker, left: &Operand, right: &Operand) -> DivisorChecker {
-        match left {
-            Operand::Old => {
-                match right {
-                    Operand::Old => {
-                        old.clone().mul_divisor_checker(&old)
-                    },
-                    Operand::Value(value) => {
-                        old.clone().mul_const(value.to_owned())
-                    }
-                }
-            }
-            Operand::Value(value) => {
-                match right {
-                    Operand::Old => {
-                        old.clone().mul_const(value.to_owned())
-                    },
-                    Operand::Value(right) => DivisorChecker::new(value * right)
-                }
-            }
+impl Operation {
+    fn apply(&self, old: &Worry, left: &Operand, right: &Operand) -> Result<Worry, String> {
+        let (left, right) = (left.value(old), right.value(old));
+        match self {
+            Operation::Add => Ok(left.add(&right)),
+            Operation::Sub => left.sub(&right),
+            Operation::Mul => Ok(left.mul(&right)),
+            Operation::Div => left.div(&right),
         }
     }
 }
 
 #[derive(Debug, Clone)]
 struct Monkey {
-    items: Vec<DivisorChecker>,
+    items: Vec<Worry>,
     operation: Operation,
     left: Operand,
     right: Operand,
-    divisor: u32,
+    divisor: u64,
     throw_true: usize,
     throw_false: usize,
     inspected: usize,
-    worry_factor: u32,
+    worry_factor: u64,
 }
 
 impl Display for Monkey {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "items={:?}, left={:?}, operation={:?}, right={:?}, div={:?}, throw_true={}, throw_false={}",
-            "self.items",
+            "items=[{}], left={:?}, operation={:?}, right={:?}, div={:?}, throw_true={}, throw_false={}",
+            join(&self.items),
             self.left,
             self.operation,
             self.right,
@@ -194,76 +229,339 @@ impl Display for Monkey {
 }
 
 impl Monkey {
-    fn do_round(&mut self) -> Vec<(usize, DivisorChecker)> {
+    fn do_round(&mut self) -> Result<Vec<(usize, Worry)>, String> {
         self.items.drain(..).map(|worry| {
             self.inspected += 1;
-            let new_worry = self.operation.apply(&worry, &self.left, &self.right);
+            let new_worry = self.operation.apply(&worry, &self.left, &self.right)?
+                .relieve(self.worry_factor);
             let throw_to = if new_worry.divisible_by(self.divisor) {
                 self.throw_true
             } else {
                 self.throw_false
             };
-            (throw_to, new_worry)
+            Ok((throw_to, new_worry))
         }).collect()
     }
 
-    fn push(&mut self, worry: DivisorChecker) {
+    fn push(&mut self, worry: Worry) {
         self.items.push(worry);
     }
 }
 
-fn show_monkeys(i: usize, monkeys: &Vec<Monkey>) {
-    println!("== After round {} ==", i + 1);
-    for (i, m) in monkeys.iter().enumerate() {
-        println!("\tMonkey {} inspected {} times: items={:?}", i, m.inspected, "m.items");
+fn join(items: &[Worry]) -> String {
+    items.iter().map(|w| w.to_string()).collect::<Vec<String>>().join(", ")
+}
+
+// the monkeys after some number of rounds. modular worries show their residue
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Snapshot {
+    round: usize,
+    items: Vec<Vec<Worry>>,
+    inspected: Vec<usize>,
+    // flow[from][to] counts every item thrown between the two so far
+    flow: Vec<Vec<usize>>,
+}
+
+impl Snapshot {
+    fn monkey_business(&self) -> u64 {
+        let mut sorted = self.inspected.clone();
+        sorted.sort();
+        sorted.into_iter().rev().take(2).map(|x| x as u64).product()
+    }
+}
+
+impl Display for Snapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "== After round {} ==", self.round)?;
+        for (i, (items, inspected)) in self.items.iter().zip(self.inspected.iter()).enumerate() {
+            writeln!(f, "Monkey {} inspected items {} times: {}", i, inspected, join(items))?;
+        }
+        Ok(())
+    }
+}
+
+fn inspections_csv(snapshots: &[Snapshot]) -> String {
+    let mut res = "round,monkey,inspected,holding,monkey_business\n".to_string();
+    for s in snapshots {
+        for (i, inspected) in s.inspected.iter().enumerate() {
+            res.push_str(&format!("{},{},{},{},{}\n", s.round, i, inspected, s.items[i].len(), s.monkey_business()));
+        }
+    }
+    res
+}
+
+fn flow_csv(snapshots: &[Snapshot]) -> String {
+    let mut res = "round,from,to,items\n".to_string();
+    for s in snapshots {
+        for (from, row) in s.flow.iter().enumerate() {
+            for (to, count) in row.iter().enumerate().filter(|(_, count)| **count > 0) {
+                res.push_str(&format!("{},{},{},{}\n", s.round, from, to, count));
+            }
+        }
+    }
+    res
+}
+
+fn items_csv(snapshots: &[Snapshot]) -> String {
+    let mut res = "round,monkey,worry\n".to_string();
+    for s in snapshots {
+        for (i, items) in s.items.iter().enumerate() {
+            for worry in items {
+                res.push_str(&format!("{},{},{}\n", s.round, i, worry));
+            }
+        }
+    }
+    res
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ParseError {
+    // position of the monkey's block in the notes
+    monkey: usize,
+    line: usize,
+    message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "monkey {}, line {}: {}", self.monkey, self.line, self.message)
+    }
+}
+
+// splits the notes into one block of (line number, text) per monkey, starting a new block
+// on a blank line or on a `Monkey` header that isn't preceded by one
+fn blocks(lines: &[String]) -> Vec<Vec<(usize, String)>> {
+    let mut res: Vec<Vec<(usize, String)>> = vec![];
+    let mut current = vec![];
+
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.split_whitespace().collect::<Vec<&str>>().join(" ");
+        if line.is_empty() || (line.to_lowercase().starts_with("monkey") && !current.is_empty()) {
+            if !current.is_empty() {
+                res.push(current);
+            }
+            current = vec![];
+        }
+        if !line.is_empty() {
+            current.push((i + 1, line));
+        }
     }
+    if !current.is_empty() {
+        res.push(current);
+    }
+
+    res
 }
 
+fn parse_monkey(index: usize, count: usize, block: &[(usize, String)]) -> Result<Monkey, ParseError> {
+    let error = |line: usize, message: String| ParseError { monkey: index, line, message };
+    let number = |line: usize, s: &str| -> Result<u64, ParseError> {
+        s.trim().parse::<u64>().map_err(|_| error(line, format!("expected a number, got {:?}", s.trim())))
+    };
+    let target = |line: usize, s: &str| -> Result<usize, ParseError> {
+        let id = s.trim().strip_prefix("throw to monkey")
+            .ok_or(error(line, format!("expected throw to monkey N, got {:?}", s.trim())))?;
+        match number(line, id)? as usize {
+            id if id < count => Ok(id),
+            id => Err(error(line, format!("there is no monkey {} to throw to", id))),
+        }
+    };
+
+    let mut items = None;
+    let mut operation = None;
+    let mut divisor = None;
+    let mut throw_true = None;
+    let mut throw_false = None;
+
+    for (line, text) in block {
+        let line = *line;
+        let (key, value) = text.split_once(':')
+            .ok_or(error(line, format!("expected `key: value`, got {:?}", text)))?;
+
+        match key.trim().to_lowercase().as_str() {
+            key if key.starts_with("monkey") => {
+                let id = number(line, &key["monkey".len()..])?;
+                if id as usize != index {
+                    return Err(error(line, format!("expected monkey {}, got {}", index, id)))
+                }
+            },
+            "starting items" => {
+                items = Some(value.split(',')
+                    .filter(|item| !item.trim().is_empty())
+                    .map(|item| number(line, item))
+                    .collect::<Result<Vec<u64>, ParseError>>()?);
+            },
+            "operation" => {
+                let expression: String = value.split_whitespace().collect();
+                let expression = expression.strip_prefix("new=")
+                    .ok_or(error(line, format!("expected new = ..., got {:?}", value.trim())))?;
+                let (at, operator) = expression.char_indices()
+                    .skip(1)
+                    .find(|(_, c)| !c.is_ascii_alphanumeric())
+                    .ok_or(error(line, format!("no operator in {:?}", expression)))?;
+
+                let parsed = (
+                    operator.to_string().parse::<Operation>(),
+                    expression[..at].parse::<Operand>(),
+                    expression[at + operator.len_utf8()..].parse::<Operand>(),
+                );
+                operation = match parsed {
+                    (Ok(Operation::Div), _, Ok(Operand::Value(0))) => return Err(error(line, "division by zero".to_string())),
+                    (Ok(operation), Ok(left), Ok(right)) => Some((operation, left, right)),
+                    (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => return Err(error(line, e)),
+                };
+            },
+            "test" => {
+                let n = value.trim().strip_prefix("divisible by")
+                    .ok_or(error(line, format!("expected divisible by N, got {:?}", value.trim())))?;
+                divisor = match number(line, n)? {
+                    0 => return Err(error(line, "can't test divisibility by zero".to_string())),
+                    n => Some(n),
+                };
+            },
+            "if true" => throw_true = Some(target(line, value)?),
+            "if false" => throw_false = Some(target(line, value)?),
+            _ => return Err(error(line, format!("unknown field {:?}", key.trim()))),
+        }
+    }
+
+    let missing = |field: &str| error(block[0].0, format!("missing {}", field));
+    let (operation, left, right) = operation.ok_or(missing("Operation"))?;
+    Ok(Monkey {
+        items: items.ok_or(missing("Starting items"))?
+            .into_iter()
+            .map(|v| Worry::new(v, None))
+            .collect(),
+        operation,
+        left,
+        right,
+        divisor: divisor.ok_or(missing("Test"))?,
+        throw_true: throw_true.ok_or(missing("If true"))?,
+        throw_false: throw_false.ok_or(missing("If false"))?,
+        inspected: 0,
+        worry_factor: 1,
+    })
+}
 
-fn simulate(lines: Vec<String>, worry_factor: u32, rounds: usize) -> u64 {
-    let re = Regex::new(r"Monkey (?P<id>\d+):\s*Starting items:\s*(?P<items>(\d+(, )?)+)\s*Operation:\s*new = (?P<left>\d+|old) (?P<operation>\+|\*|\\|-) (?P<right>\d+|old)\s*Test: divisible by (?P<div>\d+)\s*If true: throw to monkey (?P<id_true>\d+)\s*If false: throw to monkey (?P<id_false>\d+)").unwrap();
+fn parse_monkeys(lines: &[String]) -> Result<Vec<Monkey>, ParseError> {
+    let blocks = blocks(lines);
+    blocks.iter()
+        .enumerate()
+        .map(|(i, block)| parse_monkey(i, blocks.len(), block))
+        .collect()
+}
 
-    let mut monkeys = lines.chunks(7)
-        .map(|lines| {
-            let s = String::from_iter(lines.into_iter().map(|s| s.to_owned()));
-            let matches = re.captures(&s).unwrap();
-            Monkey{
-                items: matches.name("items")
-                    .unwrap()
-                    .as_str()
-                    .split(", ")
-                    .map(|s| s.parse::<u32>().unwrap())
-                    .map(DivisorChecker::new)
-                    .collect::<Vec<DivisorChecker>>(),
-                operation: matches.name("operation").unwrap().as_str().parse::<Operation>().unwrap(),
-                left: matches.name("left").unwrap().as_str().parse::<Operand>().unwrap(),
-                right: matches.name("right").unwrap().as_str().parse::<Operand>().unwrap(),
-                divisor: matches.name("div").unwrap().as_str().parse::<u32>().unwrap(),
-                throw_true: matches.name("id_true").unwrap().as_str().parse::<usize>().unwrap(),
-                throw_false: matches.name("id_false").unwrap().as_str().parse::<usize>().unwrap(),
-                inspected: 0,
-                worry_factor: worry_factor.clone(),
+struct Simulation {
+    monkeys: Vec<Monkey>,
+    round: usize,
+    flow: Vec<Vec<usize>>,
+}
+
+impl Simulation {
+    fn new(lines: &[String], worry_factor: u64) -> Result<Simulation, String> {
+        let mut monkeys = parse_monkeys(lines).map_err(|e| e.to_string())?;
+        for monkey in monkeys.iter_mut() {
+            monkey.worry_factor = worry_factor;
+        }
+
+        // dividing needs the exact worry, and with no relief to shrink it that grows
+        // without bound
+        if let (1, Some(i)) = (worry_factor, monkeys.iter().position(|m| m.operation == Operation::Div)) {
+            return Err(format!("monkey {} divides its worry, which needs relief", i));
+        }
+
+        // without relief the residues modulo every divisor are all that matter, in a u64
+        // as long as their lcm fits
+        if worry_factor == 1 {
+            let modulus = monkeys.iter().map(|m| m.divisor).try_fold(1, lcm);
+            let wide = monkeys.iter().fold(BigUint::from(1u32), |res, m| wide_lcm(&res, m.divisor));
+            for monkey in monkeys.iter_mut() {
+                monkey.items = monkey.items.iter()
+                    .map(|worry| match (worry, modulus) {
+                        (Worry::Exact(value), Some(modulus)) => Worry::new(u64::try_from(value % modulus).unwrap(), Some(modulus)),
+                        (Worry::Exact(value), None) => Worry::WideModular { value: value % &wide, modulus: wide.clone() },
+                        (modular, _) => modular.clone(),
+                    })
+                    .collect();
             }
-        })
-        .collect::<Vec<Monkey>>();
+        }
+
+        let flow = vec![vec![0; monkeys.len()]; monkeys.len()];
+        Ok(Simulation { monkeys, round: 0, flow })
+    }
 
-    for j in 0..rounds {
-        for i in 0..monkeys.len() {
-            let results =  monkeys[i].do_round();
+    fn do_round(&mut self) -> Result<(), String> {
+        for i in 0..self.monkeys.len() {
+            let results = self.monkeys[i].do_round()
+                .map_err(|e| format!("round {}, monkey {}: {}", self.round + 1, i, e))?;
             results.into_iter().for_each(|(throw_to, worry)| {
-                monkeys[throw_to].push(worry);
+                self.flow[i][throw_to] += 1;
+                self.monkeys[throw_to].push(worry);
             });
         }
+        self.round += 1;
+        Ok(())
+    }
 
-        if j == 0 || j == 19 || j == 999 || j == 1999 || j == 2999 || j == 3999 || j == 4999 || j == 5999
-            || j == 6999 || j == 7999 || j == 8999 || j == 9999 {
-            show_monkeys(j, &monkeys);
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            round: self.round,
+            items: self.monkeys.iter().map(|m| m.items.clone()).collect(),
+            inspected: self.monkeys.iter().map(|m| m.inspected).collect(),
+            flow: self.flow.clone(),
         }
     }
 
-    let mut sorted = monkeys.iter().map(|m| m.inspected).collect::<Vec<usize>>();
-    sorted.sort();
-    sorted.into_iter().rev().take(2).reduce(|x, y| x * y).unwrap() as u64
+    // plays `rounds` more rounds, snapshotting after every round the schedule picks
+    fn run(&mut self, rounds: usize, schedule: impl Fn(usize) -> bool) -> Result<Vec<Snapshot>, String> {
+        let mut res = vec![];
+        for _ in 0..rounds {
+            self.do_round()?;
+            if schedule(self.round) {
+                res.push(self.snapshot());
+            }
+        }
+        Ok(res)
+    }
+}
+
+fn simulate(lines: Vec<String>, worry_factor: u64, rounds: usize) -> u64 {
+    let mut simulation = Simulation::new(&lines, worry_factor).unwrap_or_else(|e| panic!("{}", e));
+    simulation.run(rounds, |_| false).unwrap_or_else(|e| panic!("{}", e));
+    simulation.snapshot().monkey_business()
+}
+
+// round by round statistics:
+//   show|inspections|flow|items <rounds> [--every N] [--relief N]
+pub fn run_query(lines: Vec<String>, args: &[String]) -> Result<String, String> {
+    let number = |i: usize, what: &str| -> Result<usize, String> {
+        let arg = args.get(i).ok_or(format!("missing {}", what))?;
+        arg.parse::<usize>().map_err(|_| format!("bad {}: {:?}", what, arg))
+    };
+
+    let rounds = number(1, "round count")?;
+    let mut every = 1;
+    let mut relief = 1;
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--every" => every = number(i + 1, "snapshot interval")?.max(1),
+            "--relief" => relief = number(i + 1, "relief factor")?.max(1),
+            other => return Err(format!("unknown argument: {:?}", other)),
+        }
+        i += 2;
+    }
+
+    let mut simulation = Simulation::new(&lines, relief as u64)?;
+    let snapshots = simulation.run(rounds, |round| round % every == 0)?;
+
+    match args[0].as_str() {
+        "show" => Ok(snapshots.iter().map(|s| s.to_string() + "\n").collect()),
+        "inspections" => Ok(inspections_csv(&snapshots)),
+        "flow" => Ok(flow_csv(&snapshots)),
+        "items" => Ok(items_csv(&snapshots)),
+        other => Err(format!("unknown table: {:?}", other)),
+    }
 }
 
 pub fn solve_p1(lines: Vec<String>) -> u64 {
@@ -276,88 +574,312 @@ pub fn solve_p2(lines: Vec<String>) -> u64 {
 
 #[cfg(test)]
 mod tests {
-    use crate::day11::day11::{DivisorChecker, solve_p1, solve_p2};
+    use num_bigint::BigUint;
+    use crate::day11::day11::{flow_csv, inspections_csv, items_csv, lcm, parse_monkeys, simulate, solve_p1, solve_p2, Operand, Operation, ParseError, Simulation, Worry};
+
+    fn notes(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|l| l.to_string()).collect()
+    }
+
+    fn example() -> Vec<String> {
+        notes(&[
+            "Monkey 0:",
+            "  Starting items: 79, 98",
+            "  Operation: new = old * 19",
+            "  Test: divisible by 23",
+            "    If true: throw to monkey 2",
+            "    If false: throw to monkey 3",
+            "",
+            "Monkey 1:",
+            "  Starting items: 54, 65, 75, 74",
+            "  Operation: new = old + 6",
+            "  Test: divisible by 19",
+            "    If true: throw to monkey 2",
+            "    If false: throw to monkey 0",
+            "",
+            "Monkey 2:",
+            "  Starting items: 79, 60, 97",
+            "  Operation: new = old * old",
+            "  Test: divisible by 13",
+            "    If true: throw to monkey 1",
+            "    If false: throw to monkey 3",
+            "",
+            "Monkey 3:",
+            "  Starting items: 74",
+            "  Operation: new = old + 3",
+            "  Test: divisible by 17",
+            "    If true: throw to monkey 0",
+            "    If false: throw to monkey 1",
+        ])
+    }
 
     #[test]
-    fn test_rings() {
-        for x in 1..30 {
+    fn test_worry() {
+        let modulus = (1..40).try_fold(1, lcm).unwrap();
+        for x in 1..40 {
             for y in 1..100 {
                 for j in 1..200 {
-                    assert_eq!((y + j) % x == 0, DivisorChecker::new(y).add_const(j).divisible_by(x));
-                    assert_eq!((y * j) % x == 0, DivisorChecker::new(y).mul_const(j).divisible_by(x));
-                    assert_eq!((y + j) % x == 0, DivisorChecker::new(y).add_divisor_checker(&DivisorChecker::new(j)).divisible_by(x));
-                    assert_eq!((y * j) % x == 0, DivisorChecker::new(y).mul_divisor_checker(&DivisorChecker::new(j)).divisible_by(x));
+                    for m in [Some(modulus), None] {
+                        let (wy, wj) = (Worry::new(y, m), Worry::new(j, m));
+                        assert_eq!((y + j) % x == 0, wy.add(&wy.constant(j)).divisible_by(x));
+                        assert_eq!((y * j) % x == 0, wy.mul(&wy.constant(j)).divisible_by(x));
+                        assert_eq!((y + j) % x == 0, wy.add(&wj).divisible_by(x));
+                        assert_eq!((y * j) % x == 0, wy.mul(&wj).divisible_by(x));
+                    }
                 }
             }
         }
     }
 
+    #[test]
+    fn test_worry_large() {
+        // divisors well past 30 and moduli near the top of u64
+        let modulus = [97, 101, 103, 107, 109, 113, 127, 131, 137].into_iter().try_fold(1, lcm).unwrap();
+        let big = Worry::new(modulus - 1, Some(modulus));
+        assert_eq!(big.mul(&big), Worry::new(1, Some(modulus)));
+        assert!(big.add(&big.constant(1)).divisible_by(137));
+
+        let exact = Worry::new(u64::MAX, None).mul(&Worry::new(u64::MAX, None));
+        assert_eq!(exact.to_string(), "340282366920938463426481119284349108225");
+        assert_eq!(exact.relieve(3).to_string(), "113427455640312821142160373094783036075");
+    }
+
+    #[test]
+    fn test_lcm_overflow() {
+        // three primes just under 2^32 have no lcm in a u64
+        let divisors = [4294967291u64, 4294967279, 4294967231];
+        assert_eq!(lcm(divisors[0], divisors[1]), Some(divisors[0] * divisors[1]));
+        assert_eq!(divisors.into_iter().try_fold(1, lcm), None);
+
+        let lines = notes(&[
+            "Monkey 0:",
+            "  Starting items: 4294967290",
+            "  Operation: new = old + 1",
+            "  Test: divisible by 4294967291",
+            "    If true: throw to monkey 1",
+            "    If false: throw to monkey 2",
+            "",
+            "Monkey 1:",
+            "  Starting items:",
+            "  Operation: new = old * 2",
+            "  Test: divisible by 4294967279",
+            "    If true: throw to monkey 0",
+            "    If false: throw to monkey 2",
+            "",
+            "Monkey 2:",
+            "  Starting items:",
+            "  Operation: new = old * 1",
+            "  Test: divisible by 4294967231",
+            "    If true: throw to monkey 1",
+            "    If false: throw to monkey 0",
+        ]);
+        let mut simulation = Simulation::new(&lines, 1).unwrap();
+        let modulus = BigUint::from(divisors[0]) * divisors[1] * divisors[2];
+        assert_eq!(simulation.monkeys[0].items[0], Worry::WideModular { value: BigUint::from(4294967290u64), modulus: modulus.clone() });
+        // 0 -> 1 -> 2 -> 0 in the first round, 0 -> 2 -> 0 in the second
+        assert_eq!(simulate(lines.clone(), 1, 2), 2 * 2);
+
+        // the worry stays below the modulus however long it runs
+        let snapshots = simulation.run(1000, |round| round == 1000).unwrap();
+        assert!(snapshots[0].items.iter().flatten().all(|w| matches!(w, Worry::WideModular { value, .. } if *value < modulus)));
+
+        // residues below zero wrap around the modulus
+        let one = Worry::WideModular { value: BigUint::from(1u32), modulus: modulus.clone() };
+        let below = one.sub(&one.constant(divisors[2] + 1)).unwrap();
+        assert_eq!(below, Worry::WideModular { value: &modulus - divisors[2], modulus });
+        assert!(below.divisible_by(divisors[2]) && !below.divisible_by(divisors[0]));
+    }
+
     #[test]
     fn test_solve_p1() {
-        let lines: Vec<String> = vec![
-            "Monkey 0:".to_owned(),
-            "  Starting items: 79, 98".to_owned(),
-            "  Operation: new = old * 19".to_owned(),
-            "  Test: divisible by 23".to_owned(),
-            "    If true: throw to monkey 2".to_owned(),
-            "    If false: throw to monkey 3".to_owned(),
-            "".to_owned(),
-            "Monkey 1:".to_owned(),
-            "  Starting items: 54, 65, 75, 74".to_owned(),
-            "  Operation: new = old + 6".to_owned(),
-            "  Test: divisible by 19".to_owned(),
-            "    If true: throw to monkey 2".to_owned(),
-            "    If false: throw to monkey 0".to_owned(),
-            "".to_owned(),
-            "Monkey 2:".to_owned(),
-            "  Starting items: 79, 60, 97".to_owned(),
-            "  Operation: new = old * old".to_owned(),
-            "  Test: divisible by 13".to_owned(),
-            "    If true: throw to monkey 1".to_owned(),
-            "    If false: throw to monkey 3".to_owned(),
-            "".to_owned(),
-            "Monkey 3:".to_owned(),
-            "  Starting items: 74".to_owned(),
-            "  Operation: new = old + 3".to_owned(),
-            "  Test: divisible by 17".to_owned(),
-            "    If true: throw to monkey 0".to_owned(),
-            "    If false: throw to monkey 1".to_owned(),
-        ];
-        assert_eq!(solve_p1(lines), 10605)
+        assert_eq!(solve_p1(example()), 10605)
     }
 
     #[test]
     fn test_solve_p2() {
-        let lines: Vec<String> = vec![
-            "Monkey 0:".to_owned(),
-            "  Starting items: 79, 98".to_owned(),
-            "  Operation: new = old * 19".to_owned(),
-            "  Test: divisible by 23".to_owned(),
-            "    If true: throw to monkey 2".to_owned(),
-            "    If false: throw to monkey 3".to_owned(),
-            "".to_owned(),
-            "Monkey 1:".to_owned(),
-            "  Starting items: 54, 65, 75, 74".to_owned(),
-            "  Operation: new = old + 6".to_owned(),
-            "  Test: divisible by 19".to_owned(),
-            "    If true: throw to monkey 2".to_owned(),
-            "    If false: throw to monkey 0".to_owned(),
-            "".to_owned(),
-            "Monkey 2:".to_owned(),
-            "  Starting items: 79, 60, 97".to_owned(),
-            "  Operation: new = old * old".to_owned(),
-            "  Test: divisible by 13".to_owned(),
-            "    If true: throw to monkey 1".to_owned(),
-            "    If false: throw to monkey 3".to_owned(),
-            "".to_owned(),
-            "Monkey 3:".to_owned(),
-            "  Starting items: 74".to_owned(),
-            "  Operation: new = old + 3".to_owned(),
-            "  Test: divisible by 17".to_owned(),
-            "    If true: throw to monkey 0".to_owned(),
-            "    If false: throw to monkey 1".to_owned(),
+        assert_eq!(solve_p2(example()), 2713310158)
+    }
+
+    #[test]
+    fn test_parse_layout() {
+        // no blank lines, odd spacing and capitalisation, an empty item list
+        let monkeys = parse_monkeys(&notes(&[
+            "Monkey 0:",
+            "   Starting items:79,98",
+            "Operation:   new=old*19",
+            "  test: divisible   by 23",
+            "    If true: throw to monkey 1",
+            "    If false: throw to monkey 1",
+            "Monkey 1:",
+            "  Starting items:",
+            "  Operation: new = 2 / old",
+            "  Test: divisible by 19",
+            "    If false: throw to monkey 0",
+            "    If true: throw to monkey 0",
+            "",
+            "",
+        ])).unwrap();
+
+        assert_eq!(monkeys.len(), 2);
+        assert_eq!(monkeys[0].items, vec![Worry::new(79, None), Worry::new(98, None)]);
+        assert_eq!((monkeys[0].operation, monkeys[0].divisor), (Operation::Mul, 23));
+        assert!(monkeys[1].items.is_empty());
+        assert_eq!(monkeys[1].operation, Operation::Div);
+        assert!(matches!((&monkeys[1].left, &monkeys[1].right), (Operand::Value(2), Operand::Old)));
+    }
+
+    #[test]
+    fn test_operations() {
+        let old = Worry::new(20, None);
+        let apply = |op: &str, right: u64| op.parse::<Operation>().unwrap().apply(&old, &Operand::Old, &Operand::Value(right));
+        assert_eq!(apply("+", 3), Ok(Worry::new(23, None)));
+        assert_eq!(apply("-", 3), Ok(Worry::new(17, None)));
+        assert_eq!(apply("-", 30), Err("20 - 30 is below zero".to_string()));
+        assert_eq!(apply("*", 3), Ok(Worry::new(60, None)));
+        assert_eq!(apply("/", 3), Ok(Worry::new(6, None)));
+
+        let zero = Worry::new(0, None);
+        let divide = |left: Operand, right: Operand| Operation::Div.apply(&zero, &left, &right);
+        assert_eq!(divide(Operand::Old, Operand::Value(5)), Ok(zero.clone()));
+        assert_eq!(divide(Operand::Value(5), Operand::Old), Err("5 divided by zero".to_string()));
+
+        // a modular worry that goes below zero keeps its residue: 2 - 5 is -3
+        let low = Worry::new(2, Some(30));
+        let negative = Operation::Sub.apply(&low, &Operand::Old, &Operand::Value(5)).unwrap();
+        assert_eq!(negative, Worry::new(27, Some(30)));
+        assert!(negative.divisible_by(3) && !negative.divisible_by(2) && !negative.divisible_by(5));
+
+        // subtracting without relief stays modular, past zero too
+        let lines = notes(&[
+            "Monkey 0:",
+            "  Starting items: 100, 7",
+            "  Operation: new = old - 60",
+            "  Test: divisible by 3",
+            "    If true: throw to monkey 1",
+            "    If false: throw to monkey 0",
+            "",
+            "Monkey 1:",
+            "  Starting items: 9",
+            "  Operation: new = old * 2",
+            "  Test: divisible by 5",
+            "    If true: throw to monkey 0",
+            "    If false: throw to monkey 1",
+        ]);
+        let mut simulation = Simulation::new(&lines, 1).unwrap();
+        assert!(matches!(simulation.monkeys[0].items[0], Worry::Modular { .. }));
+        simulation.run(3, |_| false).unwrap();
+        // the real worries are -80, -173 and 72
+        assert_eq!(simulation.snapshot().to_string().lines().collect::<Vec<&str>>()[1..], [
+            "Monkey 0 inspected items 6 times: 10, 7",
+            "Monkey 1 inspected items 3 times: 12",
+        ]);
+
+        // dividing needs relief, and then worries that hit zero can't be divided by
+        let lines = notes(&[
+            "Monkey 0:",
+            "  Starting items: 3, 0",
+            "  Operation: new = old / old",
+            "  Test: divisible by 2",
+            "    If true: throw to monkey 1",
+            "    If false: throw to monkey 1",
+            "",
+            "Monkey 1:",
+            "  Starting items: 1",
+            "  Operation: new = old - 2",
+            "  Test: divisible by 7",
+            "    If true: throw to monkey 0",
+            "    If false: throw to monkey 0",
+        ]);
+        assert_eq!(Simulation::new(&lines, 1).err(), Some("monkey 0 divides its worry, which needs relief".to_string()));
+        let mut simulation = Simulation::new(&lines, 2).unwrap();
+        assert_eq!(simulation.run(1, |_| false), Err("round 1, monkey 0: 0 divided by zero".to_string()));
+
+        // and exact worries can't go below zero
+        let mut lines = lines.clone();
+        lines[1] = "  Starting items: 3".to_string();
+        let mut simulation = Simulation::new(&lines, 2).unwrap();
+        assert_eq!(simulation.run(1, |_| false), Err("round 1, monkey 1: 1 - 2 is below zero".to_string()));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = |lines: &[&str]| parse_monkeys(&notes(lines)).unwrap_err();
+        let valid = [
+            "Monkey 0:",
+            "  Starting items: 79, 98",
+            "  Operation: new = old * 19",
+            "  Test: divisible by 23",
+            "    If true: throw to monkey 0",
+            "    If false: throw to monkey 0",
+            "",
         ];
-        assert_eq!(solve_p2(lines), 2713310158)
+
+        let mut lines = valid.to_vec();
+        lines.extend(["Monkey 1:", "  Starting items: 1", "  Operation: new = old % 2"]);
+        assert_eq!(err(&lines), ParseError { monkey: 1, line: 10, message: "unknown operator \"%\"".to_string() });
+
+        let mut lines = valid.to_vec();
+        lines[2] = "  Operation: new = old × 3";
+        assert_eq!(err(&lines).message, "unknown operator \"×\"");
+
+        let mut lines = valid.to_vec();
+        lines[4] = "    If true: throw to monkey 5";
+        assert_eq!(err(&lines).to_string(), "monkey 0, line 5: there is no monkey 5 to throw to");
+
+        let mut lines = valid.to_vec();
+        lines[1] = "  Starting items: 79, x";
+        assert_eq!(err(&lines).message, "expected a number, got \"x\"");
+
+        let mut lines = valid.to_vec();
+        lines[2] = "  Operation: new = old * ald";
+        assert_eq!(err(&lines).message, "expected old or a number, got \"ald\"");
+
+        let mut lines = valid.to_vec();
+        lines.remove(3);
+        assert_eq!(err(&lines).to_string(), "monkey 0, line 1: missing Test");
+
+        let mut lines = valid.to_vec();
+        lines[0] = "Monkey 3:";
+        assert_eq!(err(&lines).message, "expected monkey 0, got 3");
     }
-}
 
+    #[test]
+    fn test_snapshots() {
+        let mut simulation = Simulation::new(&example(), 3).unwrap();
+        let snapshots = simulation.run(20, |round| round == 1 || round == 20).unwrap();
+
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].to_string(), [
+            "== After round 1 ==",
+            "Monkey 0 inspected items 2 times: 20, 23, 27, 26",
+            "Monkey 1 inspected items 4 times: 2080, 25, 167, 207, 401, 1046",
+            "Monkey 2 inspected items 3 times: ",
+            "Monkey 3 inspected items 5 times: ",
+            "",
+        ].join("\n"));
+        assert_eq!(snapshots[0].flow, vec![vec![0, 0, 0, 2], vec![4, 0, 0, 0], vec![0, 1, 0, 2], vec![0, 5, 0, 0]]);
+        assert_eq!(snapshots[1].inspected, vec![101, 95, 7, 105]);
+        assert_eq!(snapshots[1].monkey_business(), 10605);
+
+        // flow out of a monkey is everything it inspected
+        for (from, row) in snapshots[1].flow.iter().enumerate() {
+            assert_eq!(row.iter().sum::<usize>(), snapshots[1].inspected[from]);
+        }
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut simulation = Simulation::new(&example(), 3).unwrap();
+        let snapshots = simulation.run(1, |_| true).unwrap();
+
+        assert_eq!(inspections_csv(&snapshots).lines().collect::<Vec<&str>>(), vec![
+            "round,monkey,inspected,holding,monkey_business",
+            "1,0,2,4,20",
+            "1,1,4,6,20",
+            "1,2,3,0,20",
+            "1,3,5,0,20",
+        ]);
+        assert_eq!(flow_csv(&snapshots).lines().nth(1), Some("1,0,3,2"));
+        assert_eq!(items_csv(&snapshots).lines().take(3).collect::<Vec<&str>>(), vec!["round,monkey,worry", "1,0,20", "1,0,23"]);
+    }
+}
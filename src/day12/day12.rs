@@ -1,163 +1,370 @@
-use std::collections::VecDeque;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
 
-fn can_move(from: (usize, usize), to: (usize, usize), heights: &Vec<Vec<u32>>) -> bool {
-    heights[from.0][from.1] + 1 >= heights[to.0][to.1]
+type Cell = (usize, usize);
+
+struct Heightmap {
+    heights: Vec<Vec<u32>>,
+    start: Cell,
+    end: Cell,
 }
 
-fn calculate_steps(start: (usize, usize), heights: &Vec<Vec<u32>>, steps: &mut Vec<Vec<Option<u32>>>) {
-    let mut to_check: VecDeque<((usize, usize), u32)> = VecDeque::from([(start, 0)]);
+impl Heightmap {
+    fn parse(lines: Vec<String>) -> Self {
+        let mut start: Cell = (0, 0);
+        let mut end: Cell = (0, 0);
+        let heights: Vec<Vec<u32>> = lines.into_iter()
+            .filter(|line| !line.is_empty())
+            .enumerate()
+            .map(|(i, line)| {
+                line.chars()
+                    .enumerate()
+                    .map(|(j, c)| {
+                        match c {
+                            'S' => {
+                                start = (i, j);
+                                0
+                            }
+                            'E' => {
+                                end = (i, j);
+                                25
+                            }
+                            _ => c as u32 - 97
+                        }
+                    }).collect::<Vec<u32>>()
+            })
+            .collect();
 
-    loop {
-        let (pos, current_steps) = match to_check.pop_front() {
-            None => {
-                return
-            },
-            Some(pos) => pos,
-        };
+        Heightmap { heights, start, end }
+    }
 
-        let lowest = match steps[pos.0][pos.1] {
-            None => current_steps,
-            Some(prev) => {
-                if prev <= current_steps {
-                    continue
-                } else {
-                    current_steps
-                }
-            }
-        };
-        steps[pos.0][pos.1] = Some(lowest);
+    fn cells(&self) -> impl Iterator<Item = Cell> + '_ {
+        (0..self.heights.len()).flat_map(move |i| (0..self.heights[i].len()).map(move |j| (i, j)))
+    }
 
-        vec![
-            (pos.0 as i32 - 1, pos.1 as i32),
-            (pos.0 as i32 + 1, pos.1 as i32),
-            (pos.0 as i32, pos.1 as i32 - 1),
-            (pos.0 as i32, pos.1 as i32 + 1),
-        ].into_iter()
+    fn neighbours(&self, pos: Cell, diagonals: bool) -> impl Iterator<Item = Cell> + '_ {
+        let offsets: &[(i32, i32)] = if diagonals { &ALL_DIRECTIONS } else { &ALL_DIRECTIONS[..4] };
+        offsets.iter()
+            .map(move |(dx, dy)| (pos.0 as i32 + dx, pos.1 as i32 + dy))
             .filter(|(x, y)| {
-                *x >= 0 && *x < heights.len() as i32 && *y >= 0 && *y < heights[0].len() as i32
+                *x >= 0 && *x < self.heights.len() as i32 && *y >= 0 && *y < self.heights[0].len() as i32
             })
             .map(|(x, y)| (x as usize, y as usize))
-            .filter(|new_pos| {
-                can_move(pos, *new_pos, heights)
-            })
-            .for_each(|new_pos| {
-                to_check.push_back((new_pos, current_steps + 1));
-            });
+    }
+
+    fn height(&self, pos: Cell) -> u32 {
+        self.heights[pos.0][pos.1]
     }
 }
 
+const ALL_DIRECTIONS: [(i32, i32); 8] = [(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (-1, 1), (1, -1), (1, 1)];
 
-pub fn solve_p1(lines: Vec<String>) -> u32 {
-    let mut start: (usize, usize) = (0, 0);
-    let mut end: (usize, usize) = (0, 0);
-    let heights: Vec<Vec<u32>> = lines.into_iter()
-        .enumerate()
-        .map(|(i, line)| {
-            line.chars()
-                .enumerate()
-                .map(|(j, c)| {
-                    match c {
-                        'S' => {
-                            start = (i, j);
-                            0
-                        }
-                        'E' => {
-                            end = (i, j);
-                            25
-                        }
-                        _ => c as u32 - 97
-                    }
-                }).collect::<Vec<u32>>()
-        })
-        .collect();
-
-    let mut steps: Vec<Vec<Option<u32>>> = heights.iter().map(|l| {
-        l.iter().map(|_| None).collect()
-    }).collect();
-
-    calculate_steps(start, &heights, &mut steps);
-    steps[end.0][end.1].unwrap()
+type CostFn = fn(i32) -> u32;
+
+// how the hiker may move, and what a step costs given the change in height
+#[derive(Debug, Clone, Copy)]
+struct MoveRule {
+    max_climb: u32,
+    max_descent: u32,
+    diagonals: bool,
+    // None counts steps
+    cost: Option<CostFn>,
+}
+
+impl MoveRule {
+    fn puzzle() -> Self {
+        MoveRule { max_climb: 1, max_descent: u32::MAX, diagonals: false, cost: None }
+    }
+
+    fn can_move(&self, from: u32, to: u32) -> bool {
+        if to > from {
+            to - from <= self.max_climb
+        } else {
+            from - to <= self.max_descent
+        }
+    }
+
+    fn step_cost(&self, from: u32, to: u32) -> u32 {
+        self.cost.map_or(1, |cost| cost(to as i32 - from as i32))
+    }
 }
 
+const COSTS: [(&str, CostFn); 2] = [
+    // every metre up is as tiring as a step
+    ("climb", |diff| 1 + diff.max(0) as u32),
+    // steep steps either way hurt, a lot
+    ("effort", |diff| 1 + (diff * diff) as u32),
+];
+
+// cheapest cost from every cell to the end, and the first step of one such route
+struct Distances {
+    cost: Vec<Vec<Option<u32>>>,
+    next: Vec<Vec<Option<Cell>>>,
+    end: Cell,
+}
+
+impl Distances {
+    fn get(&self, pos: Cell) -> Option<u32> {
+        self.cost[pos.0][pos.1]
+    }
+
+    // the cells walked from `from` up to and including the end
+    fn path(&self, from: Cell) -> Option<Vec<Cell>> {
+        self.get(from)?;
+        let mut res = vec![from];
+        let mut pos = from;
+        while let Some(next) = self.next[pos.0][pos.1] {
+            res.push(next);
+            pos = next;
+        }
+        Some(res)
+    }
+}
+
+// one search backwards from the end, stepping to the cells that could have moved onto
+// the current one. breadth first when every step costs the same, dijkstra otherwise
+fn distances_to_end(map: &Heightmap, rule: &MoveRule) -> Distances {
+    let mut cost: Vec<Vec<Option<u32>>> = map.heights.iter().map(|l| vec![None; l.len()]).collect();
+    let mut next: Vec<Vec<Option<Cell>>> = map.heights.iter().map(|l| vec![None; l.len()]).collect();
+    cost[map.end.0][map.end.1] = Some(0);
+
+    if rule.cost.is_none() {
+        let mut to_check: VecDeque<Cell> = VecDeque::from([map.end]);
+        while let Some(pos) = to_check.pop_front() {
+            let current = cost[pos.0][pos.1].unwrap();
+            for prev in map.neighbours(pos, rule.diagonals) {
+                if cost[prev.0][prev.1].is_none() && rule.can_move(map.height(prev), map.height(pos)) {
+                    cost[prev.0][prev.1] = Some(current + 1);
+                    next[prev.0][prev.1] = Some(pos);
+                    to_check.push_back(prev);
+                }
+            }
+        }
+    } else {
+        let mut to_check: BinaryHeap<Reverse<(u32, Cell)>> = BinaryHeap::from([Reverse((0, map.end))]);
+        while let Some(Reverse((current, pos))) = to_check.pop() {
+            if cost[pos.0][pos.1].is_some_and(|settled| settled < current) {
+                continue;
+            }
+            for prev in map.neighbours(pos, rule.diagonals) {
+                if !rule.can_move(map.height(prev), map.height(pos)) {
+                    continue;
+                }
+                let total = current + rule.step_cost(map.height(prev), map.height(pos));
+                if cost[prev.0][prev.1].is_none_or(|known| total < known) {
+                    cost[prev.0][prev.1] = Some(total);
+                    next[prev.0][prev.1] = Some(pos);
+                    to_check.push(Reverse((total, prev)));
+                }
+            }
+        }
+    }
+
+    Distances { cost, next, end: map.end }
+}
+
+// the lowest cell closest to the end
+fn best_start(map: &Heightmap, distances: &Distances) -> Option<Cell> {
+    map.cells()
+        .filter(|pos| map.height(*pos) == 0)
+        .filter(|pos| distances.get(*pos).is_some())
+        .min_by_key(|pos| distances.get(*pos))
+}
+
+// the route drawn the way the puzzle does: an arrow on every cell pointing at the next
+// one, E at the end and . everywhere else
+fn render_path(map: &Heightmap, path: &[Cell]) -> Vec<String> {
+    let mut grid: Vec<Vec<char>> = map.heights.iter().map(|l| vec!['.'; l.len()]).collect();
+    for pair in path.windows(2) {
+        let ((x, y), (nx, ny)) = (pair[0], pair[1]);
+        grid[x][y] = match (nx as i32 - x as i32, ny as i32 - y as i32) {
+            (-1, 0) => '^',
+            (1, 0) => 'v',
+            (0, -1) => '<',
+            (0, 1) => '>',
+            (-1, -1) => '↖',
+            (-1, 1) => '↗',
+            (1, -1) => '↙',
+            _ => '↘',
+        };
+    }
+    if let Some((x, y)) = path.last() {
+        grid[*x][*y] = 'E';
+    }
+
+    grid.into_iter().map(String::from_iter).collect()
+}
+
+pub fn solve_p1(lines: Vec<String>) -> u32 {
+    let map = Heightmap::parse(lines);
+    distances_to_end(&map, &MoveRule::puzzle()).get(map.start).unwrap()
+}
 
 pub fn solve_p2(lines: Vec<String>) -> u32 {
-    let mut start: (usize, usize) = (0, 0);
-    let mut end: (usize, usize) = (0, 0);
-    let heights: Vec<Vec<u32>> = lines.into_iter()
-        .enumerate()
-        .map(|(i, line)| {
-            line.chars()
-                .enumerate()
-                .map(|(j, c)| {
-                    match c {
-                        'S' => {
-                            start = (i, j);
-                            0
-                        }
-                        'E' => {
-                            end = (i, j);
-                            25
-                        }
-                        _ => c as u32 - 97
-                    }
-                }).collect::<Vec<u32>>()
-        })
-        .map(|h| h.iter().map(|x| 25 - x ).collect() ) // invert
-        .collect();
-
-    let mut steps: Vec<Vec<Option<u32>>> = heights.iter().map(|l| {
-        l.iter().map(|_| None).collect()
-    }).collect();
-
-    // start at the end
-    calculate_steps(end, &heights, &mut steps);
-
-    let mut min_steps :Vec<u32> = heights.iter()
-        .enumerate()
-        .flat_map(|(i, line)| {
-            line.iter()
-                .enumerate()
-                .map(|(j, h)| {
-                    (i, j)
-                })
-                .collect::<Vec<(usize, usize)>>()
-        })
-        .filter(|(x, y)| heights[*x][*y] == 25)
-        .map(|(x, y)| steps[x][y])
-        .filter(Option::is_some)
-        .map(|o| o.unwrap())
-        .collect();
-
-    min_steps.sort();
-    min_steps[0]
+    let map = Heightmap::parse(lines);
+    let distances = distances_to_end(&map, &MoveRule::puzzle());
+    distances.get(best_start(&map, &distances).unwrap()).unwrap()
+}
+
+// routes to E, drawn over the heightmap:
+//   path [options]         from S
+//   hike [options]         from whichever a is cheapest
+// options: --climb N, --descent N, --diagonal, --cost climb|effort (default counts steps)
+pub fn run_query(lines: Vec<String>, args: &[String]) -> Result<String, String> {
+    let number = |i: usize, what: &str| -> Result<u32, String> {
+        let arg = args.get(i).ok_or(format!("missing {}", what))?;
+        arg.parse::<u32>().map_err(|_| format!("bad {}: {:?}", what, arg))
+    };
+
+    let mut rule = MoveRule::puzzle();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--climb" => {
+                i += 1;
+                rule.max_climb = number(i, "climb")?;
+            },
+            "--descent" => {
+                i += 1;
+                rule.max_descent = number(i, "descent")?;
+            },
+            "--diagonal" => rule.diagonals = true,
+            "--cost" => {
+                i += 1;
+                let name = args.get(i).ok_or("missing cost")?;
+                let (_, cost) = COSTS.iter()
+                    .find(|(n, _)| n == name)
+                    .ok_or(format!("unknown cost: {:?}", name))?;
+                rule.cost = Some(*cost);
+            },
+            other => return Err(format!("unknown argument: {:?}", other)),
+        }
+        i += 1;
+    }
+
+    let map = Heightmap::parse(lines);
+    let distances = distances_to_end(&map, &rule);
+
+    let from = match args.first().map(|s| s.as_str()) {
+        Some("path") => map.start,
+        Some("hike") => best_start(&map, &distances).ok_or("no a can reach E")?,
+        _ => return Err("expected path or hike".to_string()),
+    };
+    let path = distances.path(from).ok_or("S can't reach E")?;
+
+    let mut res = format!(
+        "{} steps costing {} from {:?} to {:?}\n",
+        path.len() - 1,
+        distances.get(from).unwrap(),
+        from,
+        distances.end,
+    );
+    render_path(&map, &path).into_iter().for_each(|row| res.push_str(&(row + "\n")));
+    Ok(res)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day12::day12::{solve_p1, solve_p2};
+    use crate::day12::day12::{best_start, distances_to_end, render_path, solve_p1, solve_p2, Heightmap, MoveRule, COSTS};
 
-    #[test]
-    fn test_solve_p1() {
-        let lines: Vec<String> = vec![
+    fn example() -> Vec<String> {
+        vec![
             "Sabqponm".to_owned(),
             "abcryxxl".to_owned(),
             "accszExk".to_owned(),
             "acctuvwj".to_owned(),
             "abdefghi".to_owned(),
-        ];
-        assert_eq!(solve_p1(lines), 31)
+        ]
+    }
+
+    #[test]
+    fn test_solve_p1() {
+        assert_eq!(solve_p1(example()), 31)
     }
 
     #[test]
     fn test_solve_p2() {
-        let lines: Vec<String> = vec![
-            "Sabqponm".to_owned(),
-            "abcryxxl".to_owned(),
-            "accszExk".to_owned(),
-            "acctuvwj".to_owned(),
-            "abdefghi".to_owned(),
-        ];
-        assert_eq!(solve_p2(lines), 29)
+        assert_eq!(solve_p2(example()), 29)
+    }
+
+    #[test]
+    fn test_paths() {
+        let map = Heightmap::parse(example());
+        let distances = distances_to_end(&map, &MoveRule::puzzle());
+
+        let path = distances.path(map.start).unwrap();
+        assert_eq!(path.len(), 32);
+        assert_eq!(path.last(), Some(&map.end));
+        for (i, pair) in path.windows(2).enumerate() {
+            assert!(MoveRule::puzzle().can_move(map.height(pair[0]), map.height(pair[1])));
+            assert_eq!(distances.get(pair[0]), Some(31 - i as u32));
+        }
+
+        // both a's on the left edge next to the b column are 29 away, the first found wins
+        let best = best_start(&map, &distances).unwrap();
+        assert_eq!(distances.get(best), Some(29));
+        assert_eq!(distances.path(best).unwrap().len(), 30);
+    }
+
+    #[test]
+    fn test_render_path() {
+        let map = Heightmap::parse(example());
+        let path = vec![(0, 0), (0, 1), (1, 1), (1, 0), (2, 0), (2, 1), (2, 2), (1, 2), (0, 2)];
+        assert_eq!(render_path(&map, &path), vec![
+            ">vE.....",
+            "v<^.....",
+            ">>^.....",
+            "........",
+            "........",
+        ]);
+
+        // a cliff on every side leaves S stranded
+        let map = Heightmap::parse(vec!["Sz".to_owned(), "zE".to_owned()]);
+        let distances = distances_to_end(&map, &MoveRule::puzzle());
+        assert_eq!(distances.path(map.start), None);
+        assert_eq!(distances.get((0, 1)), Some(1));
+    }
+
+    #[test]
+    fn test_move_rules() {
+        let map = Heightmap::parse(example());
+        let steps = |rule: MoveRule| distances_to_end(&map, &rule).get(map.start);
+
+        // with any climb allowed S goes straight to E, 2 down and 5 across
+        assert_eq!(steps(MoveRule { max_climb: 25, ..MoveRule::puzzle() }), Some(7));
+        assert_eq!(steps(MoveRule { diagonals: true, ..MoveRule::puzzle() }).map(|s| s < 31), Some(true));
+        // the spiral up to the end never goes down
+        assert_eq!(steps(MoveRule { max_descent: 0, ..MoveRule::puzzle() }), Some(31));
+        assert_eq!(steps(MoveRule { max_climb: 0, ..MoveRule::puzzle() }), None);
+
+        // costs of one per step match breadth first search everywhere
+        let bfs = distances_to_end(&map, &MoveRule::puzzle());
+        let weighted = distances_to_end(&map, &MoveRule { cost: Some(|_| 1), ..MoveRule::puzzle() });
+        for pos in map.cells() {
+            assert_eq!(bfs.get(pos), weighted.get(pos));
+        }
     }
-}
 
+    #[test]
+    fn test_weighted() {
+        // straight across climbs to c and back down, around the bottom stays flat
+        let map = Heightmap::parse(vec![
+            "SbcbE".to_owned(),
+            "aaaaa".to_owned(),
+        ]);
+        let effort = MoveRule { max_climb: 25, cost: Some(COSTS[1].1), ..MoveRule::puzzle() };
+
+        let puzzle = distances_to_end(&map, &MoveRule::puzzle());
+        assert_eq!(puzzle.get(map.start), None);
+
+        let distances = distances_to_end(&map, &effort);
+        // S b c b E costs 2 + 2 + 2 + 1 + 24*24, the flat route 5 + 1 + 25*25
+        assert_eq!(distances.get(map.start), Some(2 + 2 + 2 + 1 + 24 * 24));
+        assert_eq!(render_path(&map, &distances.path(map.start).unwrap()), vec![">>>>E", "....."]);
+
+        let diagonal = MoveRule { diagonals: true, ..effort };
+        let distances = distances_to_end(&map, &diagonal);
+        assert_eq!(distances.get((1, 2)), Some(2 + 1 + 24 * 24));
+        assert_eq!(render_path(&map, &distances.path((1, 2)).unwrap()), vec!["...>E", "..↗.."]);
+    }
+}
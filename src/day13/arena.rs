@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use crate::day13::tokens::{tokenize, Children, ParseError, Token};
+
+// same ordering as Packet, walking both token streams in place. the lists being walked
+// side by side are kept on `open`, so nesting depth doesn't touch the call stack
+fn compare(tokens: &[Token], a: usize, b: usize, open: &mut Vec<(Children, Children)>) -> Ordering {
+    open.clear();
+    let (mut a, mut b) = (a, b);
+
+    loop {
+        match (tokens[a], tokens[b]) {
+            (Token::Val(x), Token::Val(y)) if x != y => return x.cmp(&y),
+            (Token::Val(_), Token::Val(_)) => {},
+            _ => open.push((Children::of(tokens, a), Children::of(tokens, b))),
+        }
+
+        // the next pair of elements, leaving every pair of lists that ran out together
+        loop {
+            let Some((left, right)) = open.last_mut() else {
+                return Ordering::Equal;
+            };
+            match (left.next(tokens), right.next(tokens)) {
+                (Some(i), Some(j)) => {
+                    (a, b) = (i, j);
+                    break;
+                },
+                (None, Some(_)) => return Ordering::Less,
+                (Some(_), None) => return Ordering::Greater,
+                (None, None) => {
+                    open.pop();
+                },
+            }
+        }
+    }
+}
+
+// every packet's tokens in one buffer, packets referred to by their position
+#[derive(Debug, Clone, Default)]
+pub struct PacketArena {
+    tokens: Vec<Token>,
+    starts: Vec<usize>,
+    // compare's stack, kept between calls so comparing doesn't allocate
+    open: RefCell<Vec<(Children, Children)>>,
+}
+
+impl PacketArena {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(&mut self, line: &str) -> Result<usize, ParseError> {
+        let start = self.tokens.len();
+        tokenize(line, &mut self.tokens)?;
+        self.starts.push(start);
+        Ok(self.starts.len() - 1)
+    }
+
+    // a packet already flattened into tokens
+    pub fn push(&mut self, tokens: &[Token]) -> usize {
+        self.starts.push(self.tokens.len());
+        self.tokens.extend_from_slice(tokens);
+        self.starts.len() - 1
+    }
+
+    pub fn cmp(&self, a: usize, b: usize) -> Ordering {
+        compare(&self.tokens, self.starts[a], self.starts[b], &mut self.open.borrow_mut())
+    }
+
+    // packet ids in packet order, stable like sorting the packets themselves
+    pub fn sorted(&self) -> Vec<usize> {
+        let mut ids: Vec<usize> = (0..self.starts.len()).collect();
+        ids.sort_by(|a, b| self.cmp(*a, *b));
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use crate::day13::arena::PacketArena;
+    use crate::day13::tokens::{tokenize, Token};
+    use crate::lcg::Lcg;
+
+    fn random(rng: &mut Lcg, depth: usize) -> String {
+        let r = rng.bits() as usize;
+        if depth == 0 || r.is_multiple_of(3) {
+            return format!("{}", r % 5);
+        }
+        let items: Vec<String> = (0..r % 4).map(|_| random(rng, depth - 1)).collect();
+        format!("[{}]", items.join(","))
+    }
+
+    #[test]
+    fn test_tokens() {
+        let mut arena = PacketArena::new();
+        assert_eq!(arena.parse("[1,[2,[]],3]"), Ok(0));
+        assert_eq!(arena.tokens.len(), 6);
+
+        // a bad line leaves nothing behind
+        assert!(arena.parse("[4,[5").is_err());
+        assert_eq!(arena.tokens.len(), 6);
+        assert_eq!(arena.starts, vec![0]);
+
+        assert_eq!(arena.parse("[[],[[7]],8]"), Ok(1));
+        let mut tokens = vec![];
+        tokenize("[1,[2]]", &mut tokens).unwrap();
+        assert_eq!(arena.push(&tokens), 2);
+        assert_eq!(arena.tokens[arena.starts[2]..], [Token::List(3), Token::Val(1), Token::List(1), Token::Val(2)]);
+
+        assert_eq!(arena.cmp(0, 2), Ordering::Greater);
+        assert_eq!(arena.cmp(1, 0), Ordering::Less);
+        assert_eq!(arena.cmp(2, 2), Ordering::Equal);
+        assert_eq!(arena.sorted(), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn test_sort_many() {
+        let mut rng = Lcg::new(1);
+        let mut arena = PacketArena::new();
+        for _ in 0..100_000 {
+            arena.parse(&random(&mut rng, 5)).unwrap();
+        }
+
+        let ids = arena.sorted();
+        assert_eq!(ids.len(), 100_000);
+        assert!(ids.windows(2).all(|w| arena.cmp(w[0], w[1]).is_le()));
+    }
+
+    #[test]
+    fn test_deep() {
+        let depth = 100_000;
+        let nested = |value: &str| "[".repeat(depth) + value + &"]".repeat(depth);
+        let mut arena = PacketArena::new();
+        arena.parse(&nested("7")).unwrap();
+        arena.parse(&nested("7")).unwrap();
+        arena.parse(&nested("6")).unwrap();
+        // a bare 8 stands in for a list holding it, all the way down
+        arena.parse("8").unwrap();
+
+        assert_eq!(arena.cmp(0, 1), Ordering::Equal);
+        assert_eq!(arena.cmp(0, 2), Ordering::Greater);
+        assert_eq!(arena.cmp(3, 0), Ordering::Greater);
+        assert_eq!(arena.sorted(), vec![2, 0, 1, 3]);
+    }
+}
@@ -1,85 +1,122 @@
 use std::cmp::Ordering;
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
-use std::str::{Chars, FromStr};
+use std::str::FromStr;
+use crate::day13::arena::PacketArena;
+use crate::day13::tokens::{tokenize, ParseError, Token};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-enum Packet {
-    Empty,
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Packet {
     Arr(Vec<Packet>),
     Val(usize),
 }
 
 impl Packet {
-    fn consume(iter: &mut Chars) -> Result<(Self, Option<char>), ()> {
-        let first = iter.next().unwrap();
-        match first {
-            '[' => {
-                let mut elems: Vec<Packet> = Vec::new();
-                loop {
-                    let (packet, current_char) = Packet::consume(iter)?;
-                    match packet {
-                        Packet::Empty => {},
-                        _ => elems.push(packet),
-                    }
-                    match current_char {
-                        Some(',') => {
-                            continue
-                        },
-                        Some(']') => {
-                            return Ok((Packet::Arr(elems), iter.next()))
-                        },
-                        None => {
-                            return Ok((Packet::Arr(elems), None))
-                        }
-                        _ => return Err(()),
-                    }
+    // the packet whose tokens start at `i`. lists still being filled are kept on a stack
+    // with the token they end at, so deep nesting doesn't touch the call stack
+    pub fn from_tokens(tokens: &[Token], i: usize) -> Packet {
+        let mut open: Vec<(Vec<Packet>, usize)> = vec![];
+        let mut next = i;
+
+        loop {
+            let mut packet = match tokens[next] {
+                Token::List(len) if len > 0 => {
+                    open.push((vec![], next + 1 + len));
+                    next += 1;
+                    continue;
+                },
+                Token::List(_) => Packet::Arr(vec![]),
+                Token::Val(x) => Packet::Val(x),
+            };
+            next += 1;
+
+            // a finished packet goes into the list around it, which may be finished too
+            loop {
+                let Some((items, end)) = open.last_mut() else {
+                    return packet;
+                };
+                items.push(packet);
+                if next < *end {
+                    break;
                 }
-            },
-            ']' => {
-                return Ok((Packet::Empty, Some(first)))
+                packet = Packet::Arr(open.pop().unwrap().0);
             }
-            _ => {
-                let mut val_string = "".to_owned();
-                val_string.push(first);
-
-                loop {
-                    let next = iter.next().unwrap();
-                    if !next.is_ascii_digit() {
-                        return val_string.parse::<usize>()
-                            .map(|x| (Packet::Val(x), Some(next)))
-                            .map_err(|_| ());
-                    }
-                    val_string.push(next);
+        }
+    }
+
+    // appends the packet flattened into tokens, the inverse of from_tokens
+    pub fn tokens(&self, out: &mut Vec<Token>) {
+        let mut open: Vec<(std::slice::Iter<Packet>, usize)> = vec![];
+        let mut packet = self;
+
+        loop {
+            match packet {
+                Packet::Val(x) => out.push(Token::Val(*x)),
+                Packet::Arr(items) => {
+                    open.push((items.iter(), out.len()));
+                    out.push(Token::List(0));
+                },
+            }
+
+            // the next element, closing every list that ran out on the way
+            loop {
+                let Some((items, start)) = open.last_mut() else {
+                    return;
+                };
+                if let Some(p) = items.next() {
+                    packet = p;
+                    break;
                 }
+                let len = out.len() - *start - 1;
+                out[*start] = Token::List(len);
+                open.pop();
             }
         }
     }
+}
 
-    fn to_string(&self) -> String {
-        match self {
-            Packet::Empty => "EMPTY".to_owned(),
-            Packet::Val(x) => format!("{}", x),
-            Packet::Arr(x) => {
-                let mut res = "[".to_owned();
-                res.push_str(&x.iter().map(|p| p.to_string()).collect::<Vec<String>>().join(","));
-                res.push(']');
-                res
-            },
+// the derived drop would recurse once per level of nesting, so nested lists are
+// moved onto a stack and dropped one at a time
+impl Drop for Packet {
+    fn drop(&mut self) {
+        let Packet::Arr(items) = self else {
+            return;
+        };
+        let mut rest = std::mem::take(items);
+        while let Some(mut p) = rest.pop() {
+            if let Packet::Arr(items) = &mut p {
+                rest.append(items);
+            }
         }
     }
 }
 
 impl FromStr for Packet {
-    type Err = ();
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Packet::consume(&mut s.chars()).map(|(p, _)| p)
+        let mut tokens = vec![];
+        tokenize(s, &mut tokens)?;
+        Ok(Packet::from_tokens(&tokens, 0))
     }
 }
 
+// canonical form: no whitespace, so it parses back to the same packet
 impl Display for Packet {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.to_string())
+        match self {
+            Packet::Val(x) => write!(f, "{}", x),
+            Packet::Arr(x) => {
+                write!(f, "[")?;
+                for (i, p) in x.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", p)?;
+                }
+                write!(f, "]")
+            },
+        }
     }
 }
 
@@ -92,26 +129,14 @@ impl PartialOrd for Packet {
 impl Ord for Packet {
     fn cmp(&self, other: &Self) -> Ordering {
         match (self, other) {
-            (Packet::Empty, Packet::Empty) => {
-                Ordering::Equal
-            },
-            (Packet::Empty, _) => {
-                Ordering::Less
-            },
-            (Packet::Val(_), Packet::Empty) => {
-                Ordering::Greater
-            },
             (Packet::Val(x), Packet::Val(y)) => {
                 x.cmp(y)
             },
-            (Packet::Val(x), Packet::Arr(y)) => {
-                Vec::from([Packet::Val(*x)]).cmp(y)
-            },
-            (Packet::Arr(_), Packet::Empty) => {
-                Ordering::Greater
+            (Packet::Val(_), Packet::Arr(y)) => {
+                std::slice::from_ref(self).cmp(y.as_slice())
             },
-            (Packet::Arr(x), Packet::Val(y)) => {
-                x.cmp(&Vec::from([Packet::Val(*y)]))
+            (Packet::Arr(x), Packet::Val(_)) => {
+                x.as_slice().cmp(std::slice::from_ref(other))
             },
             (Packet::Arr(x), Packet::Arr(y)) => {
                 x.cmp(y)
@@ -120,20 +145,140 @@ impl Ord for Packet {
     }
 }
 
+// stable, so packets that compare equal keep their input order
+fn sort_packets(packets: &[Packet]) -> Vec<Packet> {
+    let mut arena = PacketArena::new();
+    let mut tokens = vec![];
+    for p in packets {
+        tokens.clear();
+        p.tokens(&mut tokens);
+        arena.push(&tokens);
+    }
+    arena.sorted().into_iter().map(|id| packets[id].clone()).collect()
+}
+
+// drops packets identical to an earlier one. [1] and [[1]] compare equal but both stay
+fn dedup_packets(packets: &[Packet]) -> Vec<Packet> {
+    let mut seen = HashSet::new();
+    packets.iter()
+        .filter(|p| seen.insert(*p))
+        .cloned()
+        .collect()
+}
+
+// 1-based position of every marker once the markers are added to the packets and
+// everything is sorted, found by counting instead of sorting
+fn marker_ranks(packets: &[Packet], markers: &[Packet]) -> Vec<usize> {
+    markers.iter()
+        .enumerate()
+        .map(|(i, marker)| {
+            let before = packets.iter().filter(|p| *p <= marker).count();
+            // equal markers keep their order, same as a stable sort would
+            let markers_before = markers.iter()
+                .enumerate()
+                .filter(|(j, m)| if *j < i { *m <= marker } else { *m < marker })
+                .count();
+            before + markers_before + 1
+        })
+        .collect()
+}
+
+fn decoder_key(packets: &[Packet], markers: &[Packet]) -> usize {
+    marker_ranks(packets, markers).into_iter().product()
+}
+
+// why two packets are ordered the way they are: the index path to the first pair of
+// elements that differ, and those elements (None where a list ran out first)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Explanation {
+    ordering: Ordering,
+    path: Vec<usize>,
+    left: Option<Packet>,
+    right: Option<Packet>,
+}
+
+impl Display for Explanation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let at = self.path.iter().map(|i| format!("[{}]", i)).collect::<String>();
+        let side = |p: &Option<Packet>| match p {
+            Some(p) => p.to_string(),
+            None => "nothing".to_owned(),
+        };
+        let sign = match self.ordering {
+            Ordering::Less => "<",
+            Ordering::Equal => "=",
+            Ordering::Greater => ">",
+        };
+        write!(f, "at {}: {} {} {}", if at.is_empty() { "top" } else { &at }, side(&self.left), sign, side(&self.right))
+    }
+}
+
+// a value compared against a list stands in for a list holding just that value, so
+// its path continues with index 0
+fn explain(left: &Packet, right: &Packet) -> Explanation {
+    fn walk(left: &Packet, right: &Packet, path: &mut Vec<usize>) -> Option<Explanation> {
+        let found = |ordering, path: &Vec<usize>, left: Option<&Packet>, right: Option<&Packet>| Explanation {
+            ordering,
+            path: path.clone(),
+            left: left.cloned(),
+            right: right.cloned(),
+        };
+
+        let (l, r) = match (left, right) {
+            (Packet::Val(x), Packet::Val(y)) => {
+                return match x.cmp(y) {
+                    Ordering::Equal => None,
+                    ordering => Some(found(ordering, path, Some(left), Some(right))),
+                }
+            },
+            (Packet::Val(_), Packet::Arr(y)) => (std::slice::from_ref(left), y.as_slice()),
+            (Packet::Arr(x), Packet::Val(_)) => (x.as_slice(), std::slice::from_ref(right)),
+            (Packet::Arr(x), Packet::Arr(y)) => (x.as_slice(), y.as_slice()),
+        };
+
+        for i in 0..l.len().max(r.len()) {
+            path.push(i);
+            let res = match (l.get(i), r.get(i)) {
+                (Some(a), Some(b)) => walk(a, b, path),
+                (None, b) => Some(found(Ordering::Less, path, None, b)),
+                (a, None) => Some(found(Ordering::Greater, path, a, None)),
+            };
+            path.pop();
+            if res.is_some() {
+                return res;
+            }
+        }
+        None
+    }
+
+    walk(left, right, &mut vec![]).unwrap_or(Explanation {
+        ordering: Ordering::Equal,
+        path: vec![],
+        left: Some(left.clone()),
+        right: Some(right.clone()),
+    })
+}
+
+fn parse(line: &str) -> Packet {
+    Packet::from_str(line).unwrap_or_else(|e| panic!("bad packet {:?}: {}", line, e))
+}
+
 
 
 pub fn solve_p1(lines: Vec<String>) -> u32 {
-    lines.chunks(3)
-        .map(|c | (c[0].clone(), c[1].clone()))
-        .map(|(left, right)| {
-            (
-                Packet::from_str(&left).unwrap(),
-                Packet::from_str(&right).unwrap(),
-            )
+    let mut arena = PacketArena::new();
+    let pairs: Vec<(usize, usize)> = lines.chunks(3)
+        .map(|c| {
+            let left = arena.parse(&c[0]).unwrap_or_else(|e| panic!("bad packet {:?}: {}", c[0], e));
+            let right = arena.parse(&c[1]).unwrap_or_else(|e| panic!("bad packet {:?}: {}", c[1], e));
+            (left, right)
         })
+        .collect();
+
+    pairs.into_iter()
         .enumerate()
         .map(|(index, (left, right))| {
-            if left < right  {
+            if arena.cmp(left, right).is_lt() {
                 index as u32 + 1
             } else {
                 0
@@ -143,38 +288,58 @@ pub fn solve_p1(lines: Vec<String>) -> u32 {
 }
 
 pub fn solve_p2(lines: Vec<String>) -> u32 {
-    let first_div_packet = Packet::Arr(vec![Packet::Arr(vec![Packet::Val(2)])]);
-    let second_div_packet = Packet::Arr(vec![Packet::Arr(vec![Packet::Val(6)])]);
-
-    let mut packets: Vec<Packet> = lines.chunks(3)
-        .map(|c | (c[0].clone(), c[1].clone()))
-        .flat_map(|(left, right)| {
-            Vec::from([
-                Packet::from_str(&left).unwrap(),
-                Packet::from_str(&right).unwrap(),
-            ])
-        })
+    let packets: Vec<Packet> = lines.iter()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| parse(line))
         .collect();
 
-    packets.push(first_div_packet.clone());
-    packets.push(second_div_packet.clone());
-    packets.sort();
-
-    let mut index1 = 0;
-    let mut index2 = 0;
-    for (i, p) in packets.iter().enumerate() {
-        if *p == first_div_packet {
-            index1 = i + 1;
-        } else if *p == second_div_packet {
-            index2 = i + 1;
-        }
+    decoder_key(&packets, &[parse("[[2]]"), parse("[[6]]")]) as u32
+}
+
+// packet queries over every non-blank line:
+//   sort                    the packets in order, one per line
+//   dedup                   the packets with exact repeats removed
+//   key <marker>...         the markers' sorted positions and their product
+//   explain <pair>          why the 1-based pair is ordered the way it is
+pub fn run_query(lines: Vec<String>, args: &[String]) -> Result<String, String> {
+    let packets = lines.iter()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| Packet::from_str(line).map_err(|e| format!("line {}, {}", i + 1, e)))
+        .collect::<Result<Vec<Packet>, String>>()?;
+    let listing = |packets: Vec<Packet>| packets.iter().map(|p| format!("{}\n", p)).collect();
+
+    match args {
+        [command] if command == "sort" => Ok(listing(sort_packets(&packets))),
+        [command] if command == "dedup" => Ok(listing(dedup_packets(&packets))),
+        [command, markers @ ..] if command == "key" && !markers.is_empty() => {
+            let markers = markers.iter()
+                .map(|m| Packet::from_str(m).map_err(|e| format!("marker {:?}, {}", m, e)))
+                .collect::<Result<Vec<Packet>, String>>()?;
+            let ranks = marker_ranks(&packets, &markers);
+            let mut res: String = markers.iter().zip(ranks.iter()).map(|(m, r)| format!("{} {}\n", m, r)).collect();
+            res.push_str(&format!("key {}\n", ranks.iter().product::<usize>()));
+            Ok(res)
+        },
+        [command, pair] if command == "explain" => {
+            let pair = pair.parse::<usize>().map_err(|_| format!("bad pair: {:?}", pair))?;
+            if pair == 0 || 2 * pair > packets.len() {
+                return Err(format!("there is no pair {}", pair));
+            }
+            Ok(format!("{}\n", explain(&packets[2 * pair - 2], &packets[2 * pair - 1])))
+        },
+        _ => Err("expected sort, dedup, key <marker>... or explain <pair>".to_string()),
     }
-    (index1 * index2) as u32
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day13::day13::{solve_p1, solve_p2};
+    use std::str::FromStr;
+    use std::cmp::Ordering;
+    use crate::day13::day13::{decoder_key, dedup_packets, explain, marker_ranks, sort_packets, solve_p1, solve_p2, Packet};
+    use crate::day13::arena::PacketArena;
+    use crate::day13::tokens::{ParseError, Token};
+    use crate::lcg::Lcg;
 
     #[test]
     fn test_solve_p1_edge() {
@@ -250,5 +415,183 @@ mod tests {
         ];
         assert_eq!(solve_p2(lines), 140)
     }
-}
 
+    fn error(offset: usize, message: &str) -> Result<Packet, ParseError> {
+        Err(ParseError { offset, message: message.to_owned() })
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Packet::from_str("[]"), Ok(Packet::Arr(vec![])));
+        assert_eq!(Packet::from_str(" [ 1 , [ ] ,\t[23] ] "), Ok(Packet::Arr(vec![
+            Packet::Val(1),
+            Packet::Arr(vec![]),
+            Packet::Arr(vec![Packet::Val(23)]),
+        ])));
+        assert_eq!(Packet::from_str("7"), Ok(Packet::Val(7)));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Packet::from_str("[1,[2"), error(5, "expected ',' or ']', got end of input"));
+        assert_eq!(Packet::from_str(""), error(0, "expected '[' or a number, got end of input"));
+        assert_eq!(Packet::from_str("[1,]"), error(3, "expected '[' or a number, got ']'"));
+        assert_eq!(Packet::from_str("[1 2]"), error(3, "expected ',' or ']', got '2'"));
+        assert_eq!(Packet::from_str("[1]]"), error(3, "expected end of input, got ']'"));
+        assert_eq!(Packet::from_str("[-1]"), error(1, "expected '[' or a number, got '-'"));
+        assert_eq!(
+            Packet::from_str("[99999999999999999999999]"),
+            error(1, "99999999999999999999999 is too large"),
+        );
+    }
+
+    fn random(rng: &mut Lcg, depth: usize) -> Packet {
+        let r = rng.bits() as usize;
+        if depth == 0 || r.is_multiple_of(3) {
+            return Packet::Val(r % 12);
+        }
+        Packet::Arr((0..r % 5).map(|_| random(rng, depth - 1)).collect())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for line in ["[]", "[[]]", "[1,[2,[3,[4,[5,6,7]]]],8,9]", "[[],6]", "10"] {
+            assert_eq!(Packet::from_str(line).unwrap().to_string(), line);
+        }
+        assert_eq!(Packet::from_str("[ [ ], 6 ]").unwrap().to_string(), "[[],6]");
+
+        // random packets print and parse back to themselves
+        let mut rng = Lcg::new(13);
+        for _ in 0..200 {
+            let packet = random(&mut rng, 4);
+            assert_eq!(Packet::from_str(&packet.to_string()), Ok(packet));
+        }
+    }
+
+    #[test]
+    fn test_tokens() {
+        let packet = Packet::from_str("[[],[[7]],8]").unwrap();
+        let mut tokens = vec![Token::Val(9)];
+        packet.tokens(&mut tokens);
+        assert_eq!(tokens, vec![
+            Token::Val(9),
+            Token::List(5),
+            Token::List(0),
+            Token::List(2),
+            Token::List(1),
+            Token::Val(7),
+            Token::Val(8),
+        ]);
+        assert_eq!(Packet::from_tokens(&tokens, 1), packet);
+        assert_eq!(Packet::from_tokens(&tokens, 3).to_string(), "[[7]]");
+        assert_eq!(Packet::from_tokens(&tokens, 6), Packet::Val(8));
+    }
+
+    #[test]
+    fn test_matches_arena_ordering() {
+        let mut rng = Lcg::new(40);
+        let packets: Vec<Packet> = (0..300).map(|_| random(&mut rng, 4)).collect();
+
+        let mut arena = PacketArena::new();
+        for p in packets.iter() {
+            arena.parse(&p.to_string()).unwrap();
+        }
+
+        for a in 0..packets.len() {
+            for b in 0..packets.len() {
+                assert_eq!(arena.cmp(a, b), packets[a].cmp(&packets[b]), "{} vs {}", packets[a], packets[b]);
+            }
+        }
+
+        let mut sorted = packets.clone();
+        sorted.sort();
+        assert_eq!(sort_packets(&packets), sorted);
+    }
+
+    #[test]
+    fn test_deep() {
+        let depth = 100_000;
+        let line = "[".repeat(depth) + "7" + &"]".repeat(depth);
+        let packet = Packet::from_str(&line).unwrap();
+
+        let mut inner = &packet;
+        let mut levels = 0;
+        while let Packet::Arr(items) = inner {
+            assert_eq!(items.len(), 1);
+            inner = &items[0];
+            levels += 1;
+        }
+        assert_eq!(levels, depth);
+        assert_eq!(*inner, Packet::Val(7));
+
+        let mut tokens = vec![];
+        packet.tokens(&mut tokens);
+        assert_eq!(tokens.len(), depth + 1);
+        assert_eq!(Packet::from_tokens(&tokens, depth - 1).to_string(), "[7]");
+    }
+
+    fn packets(lines: &[&str]) -> Vec<Packet> {
+        lines.iter().map(|l| Packet::from_str(l).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_sort_and_dedup() {
+        let input = packets(&["[3]", "[[1]]", "[]", "[3]", "[1]", "[[]]"]);
+        assert_eq!(sort_packets(&input), packets(&["[]", "[[]]", "[[1]]", "[1]", "[3]", "[3]"]));
+        assert_eq!(dedup_packets(&input), packets(&["[3]", "[[1]]", "[]", "[1]", "[[]]"]));
+    }
+
+    #[test]
+    fn test_marker_ranks() {
+        let mut rng = Lcg::new(39);
+
+        // counting agrees with sorting everything, duplicates and equal markers included
+        for _ in 0..50 {
+            let mut random = || {
+                let items: Vec<String> = (0..rng.below(4))
+                    .map(|_| if rng.below(2) == 0 { format!("{}", rng.below(4)) } else { format!("[{}]", rng.below(4)) })
+                    .collect();
+                Packet::from_str(&format!("[{}]", items.join(","))).unwrap()
+            };
+            let input: Vec<Packet> = (0..20).map(|_| random()).collect();
+            let markers: Vec<Packet> = (0..3).map(|_| random()).collect();
+
+            let mut all: Vec<(Packet, Option<usize>)> = input.iter().map(|p| (p.clone(), None)).collect();
+            all.extend(markers.iter().enumerate().map(|(i, m)| (m.clone(), Some(i))));
+            all.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut expected = vec![0; markers.len()];
+            for (rank, (_, marker)) in all.iter().enumerate() {
+                if let Some(i) = marker {
+                    expected[*i] = rank + 1;
+                }
+            }
+            assert_eq!(marker_ranks(&input, &markers), expected);
+        }
+
+        let input = packets(&["[1,1,3,1,1]", "[[1],4]", "[9]", "[]"]);
+        assert_eq!(marker_ranks(&input, &packets(&["[[2]]", "[[6]]", "[0]"])), vec![5, 6, 2]);
+        assert_eq!(decoder_key(&input, &packets(&["[[2]]", "[[6]]"])), 20);
+    }
+
+    #[test]
+    fn test_explain() {
+        let pair = |l: &str, r: &str| explain(&Packet::from_str(l).unwrap(), &Packet::from_str(r).unwrap());
+
+        let e = pair("[1,[2,[3,[4,[5,6,7]]]],8,9]", "[1,[2,[3,[4,[5,6,0]]]],8,9]");
+        assert_eq!(e.ordering, Ordering::Greater);
+        assert_eq!(e.path, vec![1, 1, 1, 1, 2]);
+        assert_eq!(e.to_string(), "at [1][1][1][1][2]: 7 > 0");
+
+        assert_eq!(pair("[[1],[2,3,4]]", "[[1],4]").to_string(), "at [1][0]: 2 < 4");
+        assert_eq!(pair("[9]", "[[8,7,6]]").to_string(), "at [0][0]: 9 > 8");
+        assert_eq!(pair("[7,7,7]", "[7,7,7,7]").to_string(), "at [3]: nothing < 7");
+        assert_eq!(pair("[[[]]]", "[[]]").to_string(), "at [0][0]: [] > nothing");
+        assert_eq!(pair("[[1]]", "[1]").to_string(), "at top: [[1]] = [1]");
+
+        // agrees with the ordering itself
+        for (l, r) in [("[]", "[3]"), ("[[4,4],4,4]", "[[4,4],4,4,4]"), ("[10]", "[[9],1]")] {
+            let (l, r) = (Packet::from_str(l).unwrap(), Packet::from_str(r).unwrap());
+            assert_eq!(explain(&l, &r).ordering, l.cmp(&r));
+        }
+    }
+}
@@ -1 +1,3 @@
-pub mod day13;
\ No newline at end of file
+pub mod arena;
+pub mod day13;
+pub mod tokens;
\ No newline at end of file
@@ -0,0 +1,208 @@
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    // byte offset into the line
+    pub offset: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "byte {}: {}", self.offset, self.message)
+    }
+}
+
+// a packet flattened into tokens: a list is followed directly by its elements, and
+// remembers how many tokens those take up so the whole list can be skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Val(usize),
+    List(usize),
+}
+
+impl Token {
+    pub fn span(&self) -> usize {
+        match self {
+            Token::Val(_) => 1,
+            Token::List(len) => len + 1,
+        }
+    }
+}
+
+// start of every element in the list at `i`. a value stands in for the list holding
+// just that value, so it yields itself. it doesn't borrow the tokens, so walks can be
+// kept in a buffer that outlives them
+#[derive(Debug, Clone, Copy)]
+pub struct Children {
+    next: usize,
+    end: usize,
+}
+
+impl Children {
+    pub fn of(tokens: &[Token], i: usize) -> Self {
+        match tokens[i] {
+            Token::Val(_) => Children { next: i, end: i + 1 },
+            Token::List(len) => Children { next: i + 1, end: i + 1 + len },
+        }
+    }
+
+    // same tokens as `of` was given
+    pub fn next(&mut self, tokens: &[Token]) -> Option<usize> {
+        if self.next >= self.end {
+            return None;
+        }
+        let res = self.next;
+        self.next += tokens[res].span();
+        Some(res)
+    }
+}
+
+// reads the bytes of one line, whitespace allowed between tokens
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(s: &'a str) -> Self {
+        Parser { bytes: s.as_bytes(), pos: 0 }
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError { offset: self.pos, message }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        match self.bytes.get(self.pos) {
+            Some(b) => self.error(format!("expected {}, got {:?}", expected, *b as char)),
+            None => self.error(format!("expected {}, got end of input", expected)),
+        }
+    }
+
+    // lists still open are kept on a stack of their own rather than the call stack, so
+    // nesting is only limited by memory
+    fn packet(&mut self, out: &mut Vec<Token>) -> Result<(), ParseError> {
+        let mut open: Vec<usize> = vec![];
+
+        'elements: loop {
+            match self.peek() {
+                Some(b'[') => {
+                    self.pos += 1;
+                    open.push(out.len());
+                    out.push(Token::List(0));
+                    if self.peek() != Some(b']') {
+                        continue 'elements;
+                    }
+                },
+                Some(b) if b.is_ascii_digit() => self.value(out)?,
+                _ => return Err(self.unexpected("'[' or a number")),
+            }
+
+            // an element just ended, and so does every list closed right after it
+            while let Some(&start) = open.last() {
+                match self.peek() {
+                    Some(b',') => {
+                        self.pos += 1;
+                        continue 'elements;
+                    },
+                    Some(b']') => {
+                        self.pos += 1;
+                        out[start] = Token::List(out.len() - start - 1);
+                        open.pop();
+                    },
+                    _ => return Err(self.unexpected("',' or ']'")),
+                }
+            }
+            return Ok(());
+        }
+    }
+
+    fn value(&mut self, out: &mut Vec<Token>) -> Result<(), ParseError> {
+        let start = self.pos;
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_digit() {
+            self.pos += 1;
+        }
+
+        let digits = std::str::from_utf8(&self.bytes[start..self.pos]).unwrap();
+        let value = digits.parse::<usize>()
+            .map_err(|_| ParseError { offset: start, message: format!("{} is too large", digits) })?;
+        out.push(Token::Val(value));
+        Ok(())
+    }
+}
+
+// appends the tokens of one packet, leaving `out` untouched if the line doesn't parse
+pub fn tokenize(s: &str, out: &mut Vec<Token>) -> Result<(), ParseError> {
+    let len = out.len();
+    let mut parser = Parser::new(s);
+    let res = parser.packet(out).and_then(|_| match parser.peek() {
+        None => Ok(()),
+        Some(_) => Err(parser.unexpected("end of input")),
+    });
+    if res.is_err() {
+        out.truncate(len);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::day13::tokens::{tokenize, Children, ParseError, Token};
+
+    #[test]
+    fn test_tokenize() {
+        let mut tokens = vec![Token::Val(9)];
+        assert_eq!(tokenize("[1,[2,[]],3]", &mut tokens), Ok(()));
+        assert_eq!(tokens, vec![
+            Token::Val(9),
+            Token::List(5),
+            Token::Val(1),
+            Token::List(2),
+            Token::Val(2),
+            Token::List(0),
+            Token::Val(3),
+        ]);
+        let children = |i: usize| {
+            let tokens = &tokens;
+            let mut children = Children::of(tokens, i);
+            std::iter::from_fn(move || children.next(tokens)).collect::<Vec<usize>>()
+        };
+        assert_eq!(children(1), vec![2, 3, 6]);
+        assert_eq!(children(2), vec![2]);
+
+        // a bad line leaves nothing behind
+        let error = ParseError { offset: 5, message: "expected ',' or ']', got end of input".to_owned() };
+        assert_eq!(tokenize("[4,[5", &mut tokens), Err(error));
+        assert_eq!(tokens.len(), 7);
+    }
+
+    #[test]
+    fn test_tokenize_deep() {
+        let depth = 100_000;
+        let line = "[".repeat(depth) + "7" + &"]".repeat(depth);
+        let mut tokens = vec![];
+        assert_eq!(tokenize(&line, &mut tokens), Ok(()));
+
+        assert_eq!(tokens.len(), depth + 1);
+        assert_eq!(tokens[0], Token::List(depth));
+        assert_eq!(tokens[depth - 1], Token::List(1));
+        assert_eq!(tokens[depth], Token::Val(7));
+
+        // one bracket short is still an error, not a crash
+        let mut tokens = vec![];
+        assert!(tokenize(&line[..line.len() - 1], &mut tokens).is_err());
+        assert!(tokens.is_empty());
+    }
+}
@@ -1,132 +1,304 @@
-use std::collections::HashMap;
-
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Material {
     Air,
     Rock,
     Sand,
+    // settles like sand, but slides along whatever stops it first
+    Water,
+    // solid, moving whatever rests on it one column per step
+    Conveyor(i32),
+}
+
+type Segment = ((i32, i32), (i32, i32));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Source {
+    at: (i32, i32),
+    // Sand or Water
+    grain: Material,
+}
+
+// what's in the cave before anything is poured in
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Layout {
+    cells: Vec<((i32, i32), Material)>,
+    sources: Vec<Source>,
+}
+
+impl Layout {
+    // the puzzle's rock paths, with sand pouring in at 500,0
+    fn from_paths(lines: Vec<String>) -> Layout {
+        let mut layout = Layout { cells: vec![], sources: vec![Source { at: (500, 0), grain: Material::Sand }] };
+        for (start, end) in parse_rocks(lines) {
+            for x in start.0.min(end.0)..=start.0.max(end.0) {
+                for y in start.1.min(end.1)..=start.1.max(end.1) {
+                    layout.cells.push(((x, y), Material::Rock));
+                }
+            }
+        }
+        layout
+    }
+
+    // a drawing like the puzzle's, its top left cell at `origin`:
+    //   # rock, < > conveyors, o sand, ~ water, + sand source, w water source, . air
+    fn from_map(lines: Vec<String>, origin: (i32, i32)) -> Result<Layout, String> {
+        let mut layout = Layout::default();
+        for (y, line) in lines.iter().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                let at = (origin.0 + x as i32, origin.1 + y as i32);
+                match c {
+                    '.' => {},
+                    '#' => layout.cells.push((at, Material::Rock)),
+                    '<' => layout.cells.push((at, Material::Conveyor(-1))),
+                    '>' => layout.cells.push((at, Material::Conveyor(1))),
+                    'o' => layout.cells.push((at, Material::Sand)),
+                    '~' => layout.cells.push((at, Material::Water)),
+                    '+' => layout.sources.push(Source { at, grain: Material::Sand }),
+                    'w' => layout.sources.push(Source { at, grain: Material::Water }),
+                    other => return Err(format!("line {}, column {}: unknown cell {:?}", y + 1, x + 1, other)),
+                }
+            }
+        }
+        if layout.sources.is_empty() {
+            return Err("no sources in the map".to_string());
+        }
+        Ok(layout)
+    }
+}
+
+// where a falling grain tries to go, in order, before it stops
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct FallRule {
+    moves: Vec<(i32, i32)>,
+}
+
+impl FallRule {
+    // every move has to go down, otherwise a grain could keep moving forever
+    fn new(moves: Vec<(i32, i32)>) -> Result<Self, String> {
+        match moves.iter().find(|(_, dy)| *dy <= 0) {
+            Some((dx, dy)) => Err(format!("fall move {},{} doesn't go down", dx, dy)),
+            None => Ok(FallRule { moves }),
+        }
+    }
+
+    fn puzzle() -> Self {
+        // down, then down-left, then down-right
+        FallRule { moves: vec![(0, 1), (-1, 1), (1, 1)] }
+    }
+}
+
+// a cell a grain passed through, and which way it was sliding when it got there
+#[derive(Debug, Clone, Copy)]
+struct Step {
+    at: (i32, i32),
+    side: i32,
 }
 
 #[derive(Debug)]
 struct Simulation {
-    grid: HashMap<(i32, i32), Material>,
+    // row by row, columns from `left`
+    grid: Vec<Material>,
+    left: i32,
+    width: usize,
     low_x: i32,
     high_x: i32,
-    low_y: i32,
     high_y: i32,
     floor: Option<i32>,
-
+    sources: Vec<Source>,
+    rule: FallRule,
+    // where each source's last grain went, the next one follows it as far as it can
+    paths: Vec<Vec<Step>>,
 }
 
 impl Simulation {
-    fn new(with_floor: bool) -> Simulation {
-        Simulation{
-            grid: Default::default(),
-            low_x: 99999,
-            high_x: -99999,
-            low_y: 99999,
-            high_y: -99999,
-            floor: if with_floor { Some(-99999) } else { None },
-        }
-    }
+    // sized so falling grains stay on the grid: with a floor a pile spreads at most as
+    // far sideways per row as the rule moves, without one anything that reaches the
+    // lowest obstacle is lost. grains that slide off the sides are lost too
+    fn new(layout: &Layout, rule: FallRule, with_floor: bool) -> Simulation {
+        let points = || layout.cells.iter().map(|(at, _)| *at).chain(layout.sources.iter().map(|s| s.at));
+        let high_y = points().map(|p| p.1).max().unwrap();
+        let floor = if with_floor { Some(high_y + 2) } else { None };
+        let reach = rule.moves.iter().map(|(dx, _)| dx.abs()).max().unwrap_or(0).max(1);
+        let spread = floor.unwrap_or(0) * reach;
+        let low_x = points().map(|p| p.0).min().unwrap();
+        let high_x = points().map(|p| p.0).max().unwrap();
+        let left = layout.sources.iter().map(|s| s.at.0 - spread).fold(low_x, i32::min) - reach;
+        let right = layout.sources.iter().map(|s| s.at.0 + spread).fold(high_x, i32::max) + reach;
 
-    fn set(&mut self, location: (i32, i32), material: Material) {
-        self.grid.insert(location, material);
-        if location.0 < self.low_x {
-            self.low_x = location.0;
+        let width = (right - left + 1) as usize;
+        let height = (floor.unwrap_or(high_y) + 1) as usize;
+        let mut sim = Simulation {
+            grid: vec![Material::Air; width * height],
+            left,
+            width,
+            low_x,
+            high_x,
+            high_y,
+            floor,
+            sources: layout.sources.clone(),
+            rule,
+            paths: vec![vec![]; layout.sources.len()],
+        };
+
+        for (at, material) in layout.cells.iter() {
+            sim.set(*at, *material);
         }
-        if location.0 > self.high_x {
-            self.high_x = location.0;
+        if let Some(y) = floor {
+            for x in left..=right {
+                sim.set((x, y), Material::Rock);
+            }
         }
-        if location.1 < self.low_y {
-            self.low_y = location.1;
+        sim
+    }
+
+    fn index(&self, location: (i32, i32)) -> Option<usize> {
+        let column = location.0 - self.left;
+        if column < 0 || column >= self.width as i32 || location.1 < 0 {
+            return None;
         }
-        if location.1 > self.high_y {
-            self.high_y = location.1;
+        let i = location.1 as usize * self.width + column as usize;
+        if i < self.grid.len() { Some(i) } else { None }
+    }
+
+    fn set(&mut self, location: (i32, i32), material: Material) {
+        let i = self.index(location).unwrap();
+        self.grid[i] = material;
+    }
+
+    // None off the grid
+    fn at(&self, location: (i32, i32)) -> Option<Material> {
+        self.index(location).map(|i| self.grid[i])
+    }
+
+    // drops one grain from a source, returning where it settled or None once grains from
+    // it are lost or the source is buried
+    fn drop_sand(&mut self, source: usize) -> Option<(i32, i32)> {
+        let mut path = std::mem::take(&mut self.paths[source]);
+        // other sources may have filled in part of the way
+        if let Some(k) = path.iter().position(|s| self.at(s.at) != Some(Material::Air)) {
+            path.truncate(k);
         }
+        let res = self.fall(self.sources[source], &mut path);
+        self.paths[source] = path;
+        res
     }
 
-    fn mark_rock_line(&mut self, start: (i32, i32), end: (i32, i32)) {
-        let sx = if start.0 < end.0 { start.0 } else { end.0 };
-        let ex = if start.0 > end.0 { start.0 } else { end.0 };
-        let sy = if start.1 < end.1 { start.1 } else { end.1 };
-        let ey = if start.1 > end.1 { start.1 } else { end.1 };
+    fn fall(&mut self, source: Source, path: &mut Vec<Step>) -> Option<(i32, i32)> {
+        if path.is_empty() {
+            if self.at(source.at) != Some(Material::Air) {
+                return None;
+            }
+            path.push(Step { at: source.at, side: 0 });
+        }
+
+        'falling: loop {
+            let Step { at: (x, y), side } = *path.last().unwrap();
+            if self.floor.is_none() && y >= self.high_y {
+                return None;
+            }
 
-        for x in sx..ex + 1 {
-            for y in sy..ey + 1 {
-                self.set((x, y), Material::Rock);
-                self.floor = self.floor.map(|_| self.high_y + 2);
+            for (dx, dy) in self.rule.moves.iter() {
+                match self.at((x + dx, y + dy)) {
+                    None => return None,
+                    Some(Material::Air) => {
+                        path.push(Step { at: (x + dx, y + dy), side: 0 });
+                        continue 'falling;
+                    },
+                    _ => {},
+                }
             }
+
+            // stopped falling. a conveyor carries the grain along, water slides on the way
+            // it was already going or else towards whichever side is open, left first. a
+            // grain never turns back, so sliding always ends
+            let slide = match self.at((x, y + 1)) {
+                Some(Material::Conveyor(dx)) => dx,
+                _ if source.grain == Material::Water && side != 0 => side,
+                _ if source.grain == Material::Water => [-1, 1].into_iter()
+                    .find(|dx| self.at((x + dx, y)) == Some(Material::Air))
+                    .unwrap_or(0),
+                _ => 0,
+            };
+            if slide != 0 && slide != -side {
+                match self.at((x + slide, y)) {
+                    None => return None,
+                    Some(Material::Air) => {
+                        path.push(Step { at: (x + slide, y), side: slide });
+                        continue;
+                    },
+                    _ => {},
+                }
+            }
+
+            self.set((x, y), source.grain);
+            path.pop();
+            return Some((x, y));
         }
     }
 
-    fn at(&self, location: (i32, i32)) -> Material {
-        match self.grid.get(&location) {
-            None => {
-                match self.floor {
-                    None => Material::Air,
-                    Some(y) => {
-                        if location.1 == y {
-                            Material::Rock
-                        } else {
-                            Material::Air
-                        }
-                    }
+    // drops grains from every source in turn until none of them can pour any more
+    fn fill(&mut self) -> usize {
+        let mut pouring = vec![true; self.sources.len()];
+        let mut count = 0;
+        while pouring.iter().any(|p| *p) {
+            for (i, p) in pouring.iter_mut().enumerate().filter(|(_, p)| **p) {
+                match self.drop_sand(i) {
+                    Some(_) => count += 1,
+                    None => *p = false,
                 }
-            },
-            Some(x) => *x,
+            }
         }
+        count
     }
 
-    fn drop_sand(&mut self, drop_at: (i32, i32)) -> Option<(i32, i32)> {
-        if self.floor.is_none() && drop_at.1 >= self.high_y {
-            return None;
-        }
+    // with a floor every cell the sand can reach fills up, and under the puzzle's rule a
+    // cell is reachable when it's air and one of the three cells above it is
+    fn count_filled_with_floor(&self) -> usize {
+        assert!(self.floor.is_some() && self.rule == FallRule::puzzle(), "only counts the puzzle's floor");
+        let mut reached = vec![false; self.width];
+        let mut count = 0;
 
-        // look down
-        match self.at((drop_at.0, drop_at.1 + 1)) {
-            Material::Air => self.drop_sand((drop_at.0, drop_at.1 + 1)),
-            _ => {
-                // look down-left
-                match self.at((drop_at.0 - 1, drop_at.1 + 1)) {
-                    Material::Air => self.drop_sand((drop_at.0 - 1, drop_at.1 + 1)),
-                    _ => {
-                        // look down-right
-                        match self.at((drop_at.0 + 1, drop_at.1 + 1)) {
-                            Material::Air => self.drop_sand((drop_at.0 + 1, drop_at.1 + 1)),
-                            _ => {
-                                // stop falling
-                                self.set(drop_at, Material::Sand);
-                                Some(drop_at)
-                            }
-                        }
-                    }
+        for y in 0..self.floor.unwrap() {
+            let row = y as usize * self.width;
+            let above = reached;
+            reached = vec![false; self.width];
+            for i in 1..self.width - 1 {
+                let source = self.sources.iter().any(|s| s.at == (self.left + i as i32, y) && s.grain == Material::Sand);
+                if self.grid[row + i] == Material::Air && (source || above[i - 1] || above[i] || above[i + 1]) {
+                    reached[i] = true;
+                    count += 1;
                 }
             }
         }
+        count
     }
 
-    fn show(&self) {
+    fn render(&self) -> Vec<String> {
         let y_lim = match self.floor {
             Some(y) => y + 1,
             None => self.high_y + 1
         };
-        for y in self.low_y..y_lim {
-            let mut line: String = "".to_owned();
-            for x in self.low_x..self.high_x + 1 {
-                line.push(match self.at((x, y)) {
-                    Material::Air => '.',
-                    Material::Rock => '#',
-                    Material::Sand => 'o'
-                });
-            }
-            println!("{}", line);
-        }
+        (0..y_lim)
+            .map(|y| {
+                (self.low_x..self.high_x + 1)
+                    .map(|x| match (self.at((x, y)).unwrap(), self.sources.iter().find(|s| s.at == (x, y))) {
+                        (Material::Air, Some(Source { grain: Material::Water, .. })) => 'w',
+                        (Material::Air, Some(_)) => '+',
+                        (Material::Air, None) => '.',
+                        (Material::Rock, _) => '#',
+                        (Material::Sand, _) => 'o',
+                        (Material::Water, _) => '~',
+                        (Material::Conveyor(dx), _) => if dx < 0 { '<' } else { '>' },
+                    })
+                    .collect()
+            })
+            .collect()
     }
 }
 
-fn draw_rocks(lines: Vec<String>, sim: &mut Simulation) {
+fn parse_rocks(lines: Vec<String>) -> Vec<Segment> {
     lines.iter()
+        .filter(|line| !line.is_empty())
         .flat_map(|line| {
             line.split(" -> ")
                 .map(|coords| {
@@ -138,65 +310,233 @@ fn draw_rocks(lines: Vec<String>, sim: &mut Simulation) {
                 }).collect::<Vec<(i32, i32)>>()
                 .windows(2)
                 .map(|parts| (parts[0], parts[1]))
-                .collect::<Vec<((i32, i32), (i32, i32))>>()
+                .collect::<Vec<Segment>>()
         })
-        .for_each(|(from, to)| {
-            sim.mark_rock_line(from, to);
-        });
+        .collect()
 }
 
 pub fn solve_p1(lines: Vec<String>) -> u32 {
-    let mut sim = Simulation::new(false);
-    draw_rocks(lines, &mut sim);
-
-    let mut count = 0;
-    loop {
-        match sim.drop_sand((500, 0)) {
-            None => return count,
-            _ => count += 1,
-        }
-    }
+    let mut sim = Simulation::new(&Layout::from_paths(lines), FallRule::puzzle(), false);
+    sim.fill() as u32
 }
 
 pub fn solve_p2(lines: Vec<String>) -> u32 {
-    let mut sim = Simulation::new(true);
-    draw_rocks(lines, &mut sim);
-
-    let source = (500, 0);
-    let mut count = 0;
-    loop {
-        count += 1;
-        match sim.drop_sand(source) {
-            None => panic!("should never happen with a floor"),
-            Some(loc) => {
-                if loc == source {
-                    return count;
+    let sim = Simulation::new(&Layout::from_paths(lines), FallRule::puzzle(), true);
+    sim.count_filled_with_floor() as u32
+}
+
+// pours every source until the cave is full, then draws it:
+//   fill [--map X,Y] [--floor] [--fall DX,DY ...]
+// --map reads a drawing whose top left cell is X,Y instead of rock paths, --fall replaces
+// the down, down-left, down-right order
+pub fn run_query(lines: Vec<String>, args: &[String]) -> Result<String, String> {
+    let pair = |s: &str| -> Result<(i32, i32), String> {
+        let (x, y) = s.split_once(',').ok_or(format!("expected X,Y, got {:?}", s))?;
+        let number = |n: &str| n.trim().parse::<i32>().map_err(|_| format!("bad number: {:?}", n));
+        Ok((number(x)?, number(y)?))
+    };
+
+    if args.first().map(|s| s.as_str()) != Some("fill") {
+        return Err("expected fill".to_string());
+    }
+
+    let mut origin = None;
+    let mut with_floor = false;
+    let mut rule = FallRule::puzzle();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--map" => {
+                i += 1;
+                origin = Some(pair(args.get(i).ok_or("missing map origin")?)?);
+            },
+            "--floor" => with_floor = true,
+            "--fall" => {
+                let mut moves = vec![];
+                while i + 1 < args.len() && !args[i + 1].starts_with("--") {
+                    i += 1;
+                    moves.push(pair(&args[i])?);
                 }
+                rule = FallRule::new(moves)?;
             },
+            other => return Err(format!("unknown argument: {:?}", other)),
         }
+        i += 1;
+    }
+
+    let layout = match origin {
+        Some(origin) => Layout::from_map(lines, origin)?,
+        None => Layout::from_paths(lines),
+    };
+    // the grid starts at y = 0, so nothing can be drawn above it
+    let mut points = layout.cells.iter().map(|(at, _)| *at).chain(layout.sources.iter().map(|s| s.at));
+    if let Some((x, y)) = points.find(|at| at.1 < 0) {
+        return Err(format!("{},{} is above the top of the cave", x, y));
     }
+    let mut sim = Simulation::new(&layout, rule, with_floor);
+    let count = sim.fill();
+
+    let mut res = format!("{} grains settled\n", count);
+    sim.render().into_iter().for_each(|row| res.push_str(&(row + "\n")));
+    Ok(res)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day14::day14::{solve_p1, solve_p2};
+    use crate::day14::day14::{run_query, solve_p1, solve_p2, FallRule, Layout, Material, Simulation, Source};
 
-    #[test]
-    fn test_solve_p1() {
-        let lines: Vec<String> = vec![
+    fn example() -> Vec<String> {
+        vec![
             "498,4 -> 498,6 -> 496,6".to_owned(),
             "503,4 -> 502,4 -> 502,9 -> 494,9".to_owned(),
-        ];
-        assert_eq!(solve_p1(lines), 24)
+        ]
+    }
+
+    fn map(lines: &[&str]) -> Layout {
+        Layout::from_map(lines.iter().map(|l| l.to_string()).collect(), (0, 0)).unwrap()
+    }
+
+    #[test]
+    fn test_solve_p1() {
+        assert_eq!(solve_p1(example()), 24)
     }
 
     #[test]
     fn test_solve_p2() {
+        assert_eq!(solve_p2(example()), 93)
+    }
+
+    #[test]
+    fn test_drop_matches_flood() {
+        let mut layout = Layout::from_paths(example());
+        layout.cells.extend((505..=507).map(|x| ((x, 2), Material::Rock)));
+        let mut sim = Simulation::new(&layout, FallRule::puzzle(), true);
+        let flood = sim.count_filled_with_floor();
+
+        let mut count = 0;
+        let mut last = None;
+        while let Some(pos) = sim.drop_sand(0) {
+            count += 1;
+            last = Some(pos);
+        }
+        assert_eq!(count, flood);
+        assert_eq!(last, Some((500, 0)));
+        assert_eq!(sim.drop_sand(0), None);
+
+        // two sources share the pile, and the flood counts their union
+        layout.sources.push(Source { at: (520, 0), grain: Material::Sand });
+        let flood = Simulation::new(&layout, FallRule::puzzle(), true).count_filled_with_floor();
+        assert_eq!(Simulation::new(&layout, FallRule::puzzle(), true).fill(), flood);
+        assert!(flood > count);
+    }
+
+    #[test]
+    fn test_deep_cave() {
+        // one grain lands on the ledge, the next rolls off it and falls forever
+        let mut sim = Simulation::new(&Layout::from_paths(vec!["499,100000 -> 501,100000".to_owned()]), FallRule::puzzle(), false);
+        assert_eq!(sim.drop_sand(0), Some((500, 99999)));
+        assert_eq!(sim.drop_sand(0), None);
+        assert_eq!(sim.drop_sand(0), None);
+    }
+
+    #[test]
+    fn test_map() {
         let lines: Vec<String> = vec![
-            "498,4 -> 498,6 -> 496,6".to_owned(),
-            "503,4 -> 502,4 -> 502,9 -> 494,9".to_owned(),
+            "......+...".to_owned(),
+            "..........".to_owned(),
+            "..........".to_owned(),
+            "..........".to_owned(),
+            "....#...##".to_owned(),
+            "....#...#.".to_owned(),
+            "..###...#.".to_owned(),
+            "........#.".to_owned(),
+            "........#.".to_owned(),
+            "#########.".to_owned(),
         ];
-        assert_eq!(solve_p2(lines), 93)
+        let layout = Layout::from_map(lines, (494, 0)).unwrap();
+        assert_eq!(layout.sources, Layout::from_paths(example()).sources);
+
+        let mut sim = Simulation::new(&layout, FallRule::puzzle(), false);
+        assert_eq!(sim.fill(), 24);
+        assert_eq!(sim.render(), vec![
+            "......+...",
+            "..........",
+            "......o...",
+            ".....ooo..",
+            "....#ooo##",
+            "...o#ooo#.",
+            "..###ooo#.",
+            "....oooo#.",
+            ".o.ooooo#.",
+            "#########.",
+        ]);
+
+        assert_eq!(Layout::from_map(vec!["..x".to_owned()], (0, 0)), Err("line 1, column 3: unknown cell 'x'".to_string()));
+        assert_eq!(Layout::from_map(vec!["..#".to_owned()], (0, 0)), Err("no sources in the map".to_string()));
+    }
+
+    #[test]
+    fn test_fall_rule() {
+        // the mirrored cave with down-right tried before down-left fills the same way
+        let mut mirrored = Layout::from_paths(example());
+        mirrored.cells.iter_mut().for_each(|((x, _), _)| *x = 1000 - *x);
+        let rule = FallRule::new(vec![(0, 1), (1, 1), (-1, 1)]).unwrap();
+        let mut sim = Simulation::new(&mirrored, rule, false);
+        assert_eq!(sim.fill(), 24);
+
+        // straight down only piles up a column on the rock
+        let mut sim = Simulation::new(&Layout::from_paths(example()), FallRule::new(vec![(0, 1)]).unwrap(), false);
+        assert_eq!(sim.fill(), 9);
+
+        // a move that doesn't go down is rejected up front
+        assert_eq!(FallRule::new(vec![(0, 1), (1, 0)]), Err("fall move 1,0 doesn't go down".to_string()));
+        let args: Vec<String> = ["fill", "--fall", "0,1", "-1,-1"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(run_query(example(), &args), Err("fall move -1,-1 doesn't go down".to_string()));
+    }
+
+    #[test]
+    fn test_query_above_cave() {
+        let args = |s: &str| s.split(' ').map(|a| a.to_string()).collect::<Vec<String>>();
+        let lines = vec!["..+..".to_string(), "#####".to_string()];
+
+        assert_eq!(run_query(lines.clone(), &args("fill --map 0,-5")), Err("0,-4 is above the top of the cave".to_string()));
+        assert!(run_query(lines, &args("fill --map 0,0")).is_ok());
+        assert_eq!(run_query(vec!["0,-1 -> 0,3".to_string()], &args("fill")), Err("0,-1 is above the top of the cave".to_string()));
     }
-}
 
+    #[test]
+    fn test_water_and_conveyors() {
+        // water runs along the basin floor before it settles, until it overflows the rim
+        let mut sim = Simulation::new(&map(&[
+            "...w...",
+            "#.....#",
+            "#.....#",
+            "#######",
+        ]), FallRule::puzzle(), false);
+        assert_eq!(sim.fill(), 10);
+        assert_eq!(sim.render(), vec![
+            "...w...",
+            "#~~~~~#",
+            "#~~~~~#",
+            "#######",
+        ]);
+
+        // a conveyor carries sand off the end of the belt, and the third grain over the
+        // pile it makes there
+        let mut sim = Simulation::new(&map(&[
+            "..+....",
+            ".......",
+            ">>>>>..",
+            ".......",
+            "#######",
+        ]), FallRule::puzzle(), false);
+        assert_eq!(sim.fill(), 2);
+        assert_eq!(sim.render(), vec![
+            "..+....",
+            ".......",
+            ">>>>>..",
+            "....oo.",
+            "#######",
+        ]);
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
 use regex::Regex;
 
 fn man_dist(p1: &(i32, i32), p2: &(i32, i32)) -> usize {
@@ -64,82 +65,177 @@ pub fn solve_p1_with_row(lines: Vec<String>, row: i32) -> u32 {
         .unwrap()
 }
 
-fn find_empty(sensors: Vec<((i32, i32), usize)>, max_x: i32) -> (i32, i32) {
-    for x in 0i32..max_x + 1 {
-        if x % 100 == 0 {
-            println!("{} / {}", x, max_x);
+type Square = ((i64, i64), (i64, i64));
+type Point = (i32, i32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sensor {
+    at: (i32, i32),
+    beacon: (i32, i32),
+    range: i32,
+}
+
+impl Sensor {
+    fn covers(&self, p: (i32, i32)) -> bool {
+        man_dist(&self.at, &p) <= self.range as usize
+    }
+
+    // in rotated coordinates u = x + y, v = x - y the sensor covers the square
+    // [u - range, u + range] x [v - range, v + range]
+    fn rotated(&self) -> Square {
+        let u = (self.at.0 + self.at.1) as i64;
+        let v = (self.at.0 - self.at.1) as i64;
+        let r = self.range as i64;
+        ((u - r, u + r), (v - r, v + r))
+    }
+}
+
+fn get_sensors(lines: Vec<String>) -> Vec<Sensor> {
+    get_locations(lines).into_iter()
+        .map(|(at, beacon)| Sensor { at, beacon, range: man_dist(&at, &beacon) as i32 })
+        .collect()
+}
+
+// the sensors that would have reported a beacon at `p` instead of their own, so a new
+// beacon can only go where this is empty
+fn sensors_covering(sensors: &[Sensor], p: (i32, i32)) -> Vec<Sensor> {
+    sensors.iter().filter(|s| s.covers(p)).cloned().collect()
+}
+
+// (evens, odds) in lo..=hi
+fn parity_counts(lo: i64, hi: i64) -> (i64, i64) {
+    let evens = hi.div_euclid(2) - (lo - 1).div_euclid(2);
+    (evens, hi - lo + 1 - evens)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Region {
+    // low.0 <= x <= high.0 and low.1 <= y <= high.1
+    Rect { low: (i32, i32), high: (i32, i32) },
+    // u.0 <= x + y <= u.1 and v.0 <= x - y <= v.1
+    Diamond { u: (i64, i64), v: (i64, i64) },
+}
+
+impl Region {
+    fn area(&self) -> u64 {
+        match self {
+            Region::Rect { low, high } => (high.0 - low.0 + 1) as u64 * (high.1 - low.1 + 1) as u64,
+            Region::Diamond { u, v } => {
+                // x + y and x - y are both even or both odd
+                let (u_even, u_odd) = parity_counts(u.0, u.1);
+                let (v_even, v_odd) = parity_counts(v.0, v.1);
+                (u_even * v_even + u_odd * v_odd) as u64
+            },
         }
-        for y in 0i32..max_x + 1 {
-            let mut in_range = false;
-            for (sensor, dist) in &sensors {
-                let beacon_dist = man_dist(sensor, &(x, y));
-                if beacon_dist <= *dist {
-                    in_range = true;
-                    break;
-                }
-            }
-            if !in_range {
-                return (x, y)
-            }
+    }
+
+    fn cells(&self) -> Vec<(i32, i32)> {
+        match self {
+            Region::Rect { low, high } => (low.0..=high.0)
+                .flat_map(|x| (low.1..=high.1).map(move |y| (x, y)))
+                .collect(),
+            Region::Diamond { u, v } => (u.0..=u.1)
+                .flat_map(|u| {
+                    ((u + v.0 + 1).div_euclid(2)..=(u + v.1).div_euclid(2)).map(move |x| (x as i32, (u - x) as i32))
+                })
+                .collect(),
         }
     }
-    panic!("no empty locations found");
 }
 
-fn solve_p2_with_cap(lines: Vec<String>, max_x: i32) -> u128 {
-    let sensors: Vec<((i32, i32), usize)> = get_locations(lines).into_iter()
-        .map(|(sensor, beacon)| (sensor, man_dist(&sensor, &beacon)))
-        .collect();
+impl Display for Region {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Region::Rect { low, high } => write!(f, "x={}..{}, y={}..{}", low.0, high.0, low.1, high.1),
+            Region::Diamond { u, v } => write!(f, "x+y={}..{}, x-y={}..{}", u.0, u.1, v.0, v.1),
+        }
+    }
+}
+
+// the parts of the rectangle low..=high that no sensor covers. rotated, the sensors are
+// axis aligned squares, so cutting the plane at every square's edges leaves a small grid
+// of blocks that are each either entirely covered or not. uncovered blocks inside the
+// rectangle are diamonds, ones cut off by its sides are split into rows
+fn uncovered_regions(sensors: &[Sensor], low: (i32, i32), high: (i32, i32)) -> Vec<Region> {
+    let (low_u, high_u) = ((low.0 + low.1) as i64, (high.0 + high.1) as i64);
+    let (low_v, high_v) = ((low.0 - high.1) as i64, (high.0 - low.1) as i64);
+    let squares: Vec<Square> = sensors.iter().map(|s| s.rotated()).collect();
 
-    let candidates: Vec<(i32, i32)> = sensors.iter()
-        .flat_map(|(sensor, distance)| {
-            // get a vec of all location JUST on the border of this sensor
-            let mut res = Vec::<(i32, i32)>::new();
-            let dist = *distance as i32;
-
-            for i in 0..dist + 1 {
-                // d=1
-                // .....
-                // ..#..
-                // .#X#.
-                // ..#..
-                // .....
-
-                // top right
-                res.push((sensor.0 + i, sensor.1 + dist - i + 1));
-                res.push((sensor.0 + i + 1, sensor.1 + dist - i + 1));
-
-                // bottom right
-                res.push((sensor.0 + dist + 1 - i, sensor.1 - i));
-                res.push((sensor.0 + dist + 1 - i, sensor.1 - i - 1));
-
-                // bottom left
-                res.push((sensor.0 - i, sensor.1 - dist + i - 1));
-                res.push((sensor.0 - i - 1, sensor.1 - dist + i - 1));
-
-                // top left
-                res.push((sensor.0 - dist - 1 + i, sensor.1 + i));
-                res.push((sensor.0 - dist - 1 + i, sensor.1 + i + 1));
+    // block edges, each the first coordinate of a block
+    let cuts = |low: i64, high: i64, side: fn(&Square) -> (i64, i64)| {
+        let mut res: Vec<i64> = squares.iter()
+            .flat_map(|square| { let (a, b) = side(square); [a, b + 1] })
+            .chain([low, high + 1])
+            .filter(|c| *c >= low && *c <= high + 1)
+            .collect();
+        res.sort();
+        res.dedup();
+        res
+    };
+    let us = cuts(low_u, high_u, |square| square.0);
+    let vs = cuts(low_v, high_v, |square| square.1);
+
+    let mut res = vec![];
+    for u_block in us.windows(2) {
+        for v_block in vs.windows(2) {
+            let (u, v) = ((u_block[0], u_block[1] - 1), (v_block[0], v_block[1] - 1));
+            let covered = squares.iter()
+                .any(|((u1, u2), (v1, v2))| *u1 <= u.0 && u.0 <= *u2 && *v1 <= v.0 && v.0 <= *v2);
+            if covered {
+                continue;
             }
 
-            res
-        })
-        .filter(|(x, y)| *x >= 0 && *x <= max_x && *y >= 0 && *y <= max_x)
-        .collect();
+            // the block's corners, doubled to stay on whole numbers
+            let inside = 2 * low.0 as i64 <= u.0 + v.0 && u.1 + v.1 <= 2 * high.0 as i64
+                && 2 * low.1 as i64 <= u.0 - v.1 && u.1 - v.0 <= 2 * high.1 as i64;
+            if inside {
+                let diamond = Region::Diamond { u, v };
+                if diamond.area() > 0 {
+                    res.push(diamond);
+                }
+                continue;
+            }
 
-    for loc in &candidates {
-        let mut in_range = false;
-        for (sensor_loc, sense_dist) in &sensors {
-            if man_dist(loc, sensor_loc) <= *sense_dist {
-                in_range = true;
-                break
+            let mut rows: Vec<Region> = vec![];
+            for y in (u.0 - v.1).div_euclid(2).max(low.1 as i64)..=(u.1 - v.0).div_euclid(2).min(high.1 as i64) {
+                let from = (low.0 as i64).max(u.0 - y).max(v.0 + y) as i32;
+                let to = (high.0 as i64).min(u.1 - y).min(v.1 + y) as i32;
+                if from > to {
+                    continue;
+                }
+                let y = y as i32;
+                match rows.last_mut() {
+                    // the row above spans the same columns, so grow it
+                    Some(Region::Rect { low, high }) if low.0 == from && high.0 == to && high.1 == y - 1 => high.1 = y,
+                    _ => rows.push(Region::Rect { low: (from, y), high: (to, y) }),
+                }
             }
-        }
-        if !in_range {
-            return (loc.0 as u128) * 4000000 + loc.1 as u128
+            res.extend(rows);
         }
     }
-    panic!("distress beacon location not found")
+    res
+}
+
+// every cell with low.0 <= x <= high.0 and low.1 <= y <= high.1 that no sensor covers
+fn uncovered(sensors: &[Sensor], low: (i32, i32), high: (i32, i32)) -> Vec<(i32, i32)> {
+    let mut res: Vec<(i32, i32)> = uncovered_regions(sensors, low, high).iter()
+        .flat_map(|region| region.cells())
+        .collect();
+    res.sort();
+    res
+}
+
+fn covered_area(sensors: &[Sensor], low: (i32, i32), high: (i32, i32)) -> u64 {
+    let uncovered: u64 = uncovered_regions(sensors, low, high).iter().map(|r| r.area()).sum();
+    Region::Rect { low, high }.area() - uncovered
+}
+
+fn solve_p2_with_cap(lines: Vec<String>, max_x: i32) -> u128 {
+    let sensors = get_sensors(lines);
+    let loc = *uncovered(&sensors, (0, 0), (max_x, max_x))
+        .first()
+        .expect("distress beacon location not found");
+    (loc.0 as u128) * 4000000 + loc.1 as u128
 }
 
 pub fn solve_p1(lines: Vec<String>) -> u32 {
@@ -150,13 +246,59 @@ pub fn solve_p2(lines: Vec<String>) -> u128 {
     solve_p2_with_cap(lines, 4000000)
 }
 
+// coverage of the sensors' exclusion zones, rectangles given by two opposite corners:
+//   area <x1,y1> <x2,y2>       how many cells of the rectangle are covered
+//   gaps <x1,y1> <x2,y2>       the uncovered parts of the rectangle
+//   who <x,y>                  the sensors covering a cell, and whether a beacon could go there
+pub fn run_query(lines: Vec<String>, args: &[String]) -> Result<String, String> {
+    let point = |i: usize| -> Result<(i32, i32), String> {
+        let arg = args.get(i).ok_or("missing x,y")?;
+        let (x, y) = arg.split_once(',').ok_or(format!("expected x,y, got {:?}", arg))?;
+        let number = |n: &str| n.trim().parse::<i32>().map_err(|_| format!("bad number: {:?}", n));
+        Ok((number(x)?, number(y)?))
+    };
+    let rect = || -> Result<(Point, Point), String> {
+        let (a, b) = (point(1)?, point(2)?);
+        Ok(((a.0.min(b.0), a.1.min(b.1)), (a.0.max(b.0), a.1.max(b.1))))
+    };
+
+    let sensors = get_sensors(lines.into_iter().filter(|l| !l.is_empty()).collect());
+    match args.first().map(|s| s.as_str()) {
+        Some("area") => {
+            let (low, high) = rect()?;
+            let area = Region::Rect { low, high }.area();
+            Ok(format!("{} of {} cells covered\n", covered_area(&sensors, low, high), area))
+        },
+        Some("gaps") => {
+            let (low, high) = rect()?;
+            Ok(uncovered_regions(&sensors, low, high).iter()
+                .map(|r| format!("{} ({} cells)\n", r, r.area()))
+                .collect())
+        },
+        Some("who") => {
+            let p = point(1)?;
+            let covering = sensors_covering(&sensors, p);
+            let mut res: String = covering.iter()
+                .map(|s| format!("sensor at {:?}, range {}, {} away\n", s.at, s.range, man_dist(&s.at, &p)))
+                .collect();
+            if sensors.iter().any(|s| s.beacon == p) {
+                res.push_str("already a beacon\n");
+            } else {
+                res.push_str(if covering.is_empty() { "a beacon could be here\n" } else { "no beacon can be here\n" });
+            }
+            Ok(res)
+        },
+        _ => Err("expected area, gaps or who".to_string()),
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::day15::day15::{solve_p1_with_row, solve_p2_with_cap};
+    use crate::day15::day15::{covered_area, get_sensors, man_dist, sensors_covering, solve_p1_with_row, solve_p2_with_cap, uncovered, uncovered_regions, Region, Sensor};
+    use crate::lcg::Lcg;
 
-    #[test]
-    fn test_solve_p1() {
-        let lines: Vec<String> = vec![
+    fn example() -> Vec<String> {
+        vec![
             "Sensor at x=2, y=18: closest beacon is at x=-2, y=15".to_owned(),
             "Sensor at x=9, y=16: closest beacon is at x=10, y=16".to_owned(),
             "Sensor at x=13, y=2: closest beacon is at x=15, y=3".to_owned(),
@@ -171,29 +313,75 @@ mod tests {
             "Sensor at x=16, y=7: closest beacon is at x=15, y=3".to_owned(),
             "Sensor at x=14, y=3: closest beacon is at x=15, y=3".to_owned(),
             "Sensor at x=20, y=1: closest beacon is at x=15, y=3".to_owned(),
-        ];
-        assert_eq!(solve_p1_with_row(lines, 10), 26)
+        ]
+    }
+
+    #[test]
+    fn test_solve_p1() {
+        assert_eq!(solve_p1_with_row(example(), 10), 26)
     }
 
     #[test]
     fn test_solve_p2() {
-        let lines: Vec<String> = vec![
-            "Sensor at x=2, y=18: closest beacon is at x=-2, y=15".to_owned(),
-            "Sensor at x=9, y=16: closest beacon is at x=10, y=16".to_owned(),
-            "Sensor at x=13, y=2: closest beacon is at x=15, y=3".to_owned(),
-            "Sensor at x=12, y=14: closest beacon is at x=10, y=16".to_owned(),
-            "Sensor at x=10, y=20: closest beacon is at x=10, y=16".to_owned(),
-            "Sensor at x=14, y=17: closest beacon is at x=10, y=16".to_owned(),
-            "Sensor at x=8, y=7: closest beacon is at x=2, y=10".to_owned(),
-            "Sensor at x=2, y=0: closest beacon is at x=2, y=10".to_owned(),
-            "Sensor at x=0, y=11: closest beacon is at x=2, y=10".to_owned(),
-            "Sensor at x=20, y=14: closest beacon is at x=25, y=17".to_owned(),
-            "Sensor at x=17, y=20: closest beacon is at x=21, y=22".to_owned(),
-            "Sensor at x=16, y=7: closest beacon is at x=15, y=3".to_owned(),
-            "Sensor at x=14, y=3: closest beacon is at x=15, y=3".to_owned(),
-            "Sensor at x=20, y=1: closest beacon is at x=15, y=3".to_owned(),
-        ];
-        assert_eq!(solve_p2_with_cap(lines, 20), 56000011)
+        assert_eq!(solve_p2_with_cap(example(), 20), 56000011)
+    }
+
+    #[test]
+    fn test_uncovered() {
+        let sensors = get_sensors(example());
+        assert_eq!(uncovered(&sensors, (0, 0), (20, 20)), vec![(14, 11)]);
+        assert_eq!(uncovered(&sensors, (14, 11), (14, 11)), vec![(14, 11)]);
+        assert_eq!(uncovered(&sensors, (0, 0), (13, 20)), vec![]);
+
+        assert_eq!(covered_area(&sensors, (0, 0), (20, 20)), 440);
+        assert_eq!(uncovered_regions(&sensors, (0, 0), (20, 20)), vec![Region::Diamond { u: (25, 25), v: (3, 3) }]);
+        assert_eq!(Region::Diamond { u: (25, 25), v: (3, 3) }.to_string(), "x+y=25..25, x-y=3..3");
+        assert!(sensors_covering(&sensors, (14, 11)).is_empty());
+        assert_eq!(
+            sensors_covering(&sensors, (14, 10)).iter().map(|s| s.at).collect::<Vec<(i32, i32)>>(),
+            vec![(8, 7), (16, 7)],
+        );
+
+        // far from every sensor the whole rectangle is one diamond's worth of gaps
+        let regions = uncovered_regions(&sensors, (100, 100), (103, 101));
+        assert_eq!(regions.iter().map(|r| r.area()).sum::<u64>(), 8);
+        assert_eq!(covered_area(&sensors, (100, 100), (103, 101)), 0);
     }
-}
 
+    #[test]
+    fn test_uncovered_matches_brute_force() {
+        let mut rng = Lcg::new(15);
+        let mut next = |n: i32| rng.below(n as usize) as i32;
+
+        for _ in 0..100 {
+            let sensors: Vec<Sensor> = (0..next(6) + 1)
+                .map(|_| {
+                    let at = (next(40) - 10, next(40) - 10);
+                    let beacon = (at.0 + next(17) - 8, at.1 + next(17) - 8);
+                    let range = (at.0 - beacon.0).abs() + (at.1 - beacon.1).abs();
+                    Sensor { at, beacon, range }
+                })
+                .collect();
+            let low = (next(20), next(20));
+            let high = (low.0 + next(15), low.1 + next(15));
+
+            let mut expected = vec![];
+            for x in low.0..=high.0 {
+                for y in low.1..=high.1 {
+                    if !sensors.iter().any(|s| man_dist(&s.at, &(x, y)) <= s.range as usize) {
+                        expected.push((x, y));
+                    }
+                }
+            }
+            assert_eq!(uncovered(&sensors, low, high), expected, "{:?} in {:?} to {:?}", sensors, low, high);
+
+            let area = ((high.0 - low.0 + 1) * (high.1 - low.1 + 1)) as u64;
+            assert_eq!(covered_area(&sensors, low, high), area - expected.len() as u64);
+            let regions = uncovered_regions(&sensors, low, high);
+            assert_eq!(regions.iter().map(|r| r.area()).sum::<u64>(), expected.len() as u64);
+
+            let p = (low.0 + next(high.0 - low.0 + 1), low.1 + next(high.1 - low.1 + 1));
+            assert_eq!(sensors_covering(&sensors, p).is_empty(), expected.contains(&p));
+        }
+    }
+}
@@ -1,366 +1,784 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
 use regex::Regex;
 
-fn contains(opened: u64, key: u64) -> bool {
-    opened & key > 0
-}
-
-fn set(opened: u64, key: u64) -> u64 {
-    opened | key
-}
+// every valve's flow rate and the valves its tunnels lead to, by index
+type Valves = Vec<(usize, Vec<usize>)>;
 
-fn parse_lines(lines: Vec<String>) -> (HashMap<String, u64>, HashMap::<u64, (usize, Vec<u64>)>) {
+fn parse_lines(lines: Vec<String>) -> (HashMap<String, usize>, Valves) {
     let re = Regex::new(r"Valve (?P<key>[A-Z]+) has flow rate=(?P<rate>\d+); tunnels? leads? to valves? (?P<leads_to>([A-Z]+(, )?)+)").unwrap();
     let keys_str = lines.into_iter()
         .map(|line| {
             let captures = re.captures(&line).unwrap();
 
-            let key = (&captures[1]).to_owned();
-            let rate = (&captures[2]).parse::<usize>().unwrap();
-            let leads_to: Vec<String> = (&captures[3]).split(", ")
+            let key = captures[1].to_owned();
+            let rate = captures[2].parse::<usize>().unwrap();
+            let leads_to: Vec<String> = captures[3].split(", ")
                 .map(|s| s.to_owned())
                 .collect::<Vec<String>>();
 
             (key, (rate, leads_to))
-
-            // valves.insert(key, (rate, leads_to));
         })
         .collect::<Vec<(String, (usize, Vec<String>))>>();
 
-    let keys_map: HashMap<String, u64> = keys_str.iter()
+    let keys_map: HashMap<String, usize> = keys_str.iter()
         .map(|(key, _)| key)
         .enumerate()
         .map(|(i, key)| {
-            (key.clone(), 1 << i)
+            (key.clone(), i)
         })
         .collect();
 
-    let mut valves = HashMap::<u64, (usize, Vec<u64>)>::new();
-    keys_str.iter()
-        .for_each(|(key, (rate, leads_to))| {
-            valves.insert(
-                keys_map.get(key).unwrap().to_owned(),
-                (
-                    rate.to_owned(),
-                    leads_to.iter().map(|k| keys_map.get(k).unwrap()).cloned().collect(),
-                )
-            );
-        });
+    let valves: Valves = keys_str.iter()
+        .map(|(_, (rate, leads_to))| {
+            (
+                rate.to_owned(),
+                leads_to.iter().map(|k| keys_map.get(k).unwrap()).cloned().collect(),
+            )
+        })
+        .collect();
 
     (keys_map, valves)
 }
 
-fn recurse_p1(
-    cache: &mut HashMap<(usize, u64, u64), usize>,
-    opened: u64,
-    valves: &HashMap::<u64, (usize, Vec<u64>)>,
-    position: u64,
-    time: usize,
-) -> usize {
-    if time == 0 {
-        return 0
+// the valves worth opening, and how many minutes it takes to walk between them
+#[derive(Debug, Clone)]
+struct Network {
+    keys: Vec<usize>,
+    rates: Vec<usize>,
+    dist: Vec<Vec<usize>>,
+    from_start: Vec<usize>,
+}
+
+impl Network {
+    // shortest paths from the start and every positive flow valve, by breadth first
+    // search over the tunnels. valves that can't be reached are left out
+    fn new(keys_map: &HashMap<String, usize>, valves: &Valves, start: &str) -> Network {
+        let distances = |from: usize| -> HashMap<usize, usize> {
+            let mut res = HashMap::from([(from, 0)]);
+            let mut to_check = VecDeque::from([from]);
+            while let Some(pos) = to_check.pop_front() {
+                let steps = res[&pos];
+                for next in valves[pos].1.iter() {
+                    if !res.contains_key(next) {
+                        res.insert(*next, steps + 1);
+                        to_check.push_back(*next);
+                    }
+                }
+            }
+            res
+        };
+
+        let start = distances(keys_map[start]);
+        let mut useful: Vec<(&String, usize)> = keys_map.iter()
+            .map(|(name, key)| (name, *key))
+            .filter(|(_, key)| valves[*key].0 > 0 && start.contains_key(key))
+            .collect();
+        useful.sort();
+
+        let from_useful: Vec<HashMap<usize, usize>> = useful.iter().map(|(_, key)| distances(*key)).collect();
+        Network {
+            keys: useful.iter().map(|(_, key)| *key).collect(),
+            rates: useful.iter().map(|(_, key)| valves[*key].0).collect(),
+            dist: from_useful.iter().map(|d| useful.iter().map(|(_, key)| d[key]).collect()).collect(),
+            from_start: useful.iter().map(|(_, key)| start[key]).collect(),
+        }
     }
+}
 
-    // check cache
-    let key = (time, position.clone(), opened.clone());
-    match cache.get(&key) {
-        Some(pressure) => return pressure.to_owned(),
-        None => {},
+// tries every order of opening valves one agent can manage in time, and keeps the most
+// pressure released for each set of valves opened. nothing is pruned, so it's exact
+fn best_per_subset(network: &Network, time: usize) -> HashMap<Opened, usize> {
+    fn visit(
+        network: &Network,
+        pos: Option<usize>,
+        time_left: usize,
+        opened: &mut Opened,
+        pressure: usize,
+        best: &mut HashMap<Opened, usize>,
+    ) {
+        let entry = best.entry(opened.clone()).or_insert(0);
+        *entry = (*entry).max(pressure);
+
+        for next in opened.still_closed() {
+            let walk = match pos {
+                None => network.from_start[next],
+                Some(pos) => network.dist[pos][next],
+            };
+            // a valve opened with no time left releases nothing
+            if walk + 1 < time_left {
+                let left = time_left - walk - 1;
+                opened.open(next);
+                visit(network, Some(next), left, opened, pressure + network.rates[next] * left, best);
+                opened.close(next);
+            }
+        }
     }
 
-    let res = {
-        let mut options = Vec::<usize>::new();
-        let (rate, leads_to) = valves.get(&position).unwrap();
+    let mut best = HashMap::new();
+    visit(network, None, time, &mut Opened::new(network.rates.len()), 0, &mut best);
+    best
+}
 
-        if *rate > 0 && !contains(opened, position) {
-            options.push(
-                (time - 1) * rate + recurse_p1(cache, set(opened, position), valves, position.clone(), time - 1)
-            )
+// the best a team can do together is the best sets of valves they open without any
+// overlap. sets are tried by pressure, so once the agents still to pick can't lift the
+// total past the best found even at the current set's pressure, the rest can be skipped.
+// returns the pressure and each agent's set
+fn best_team(best: &HashMap<Opened, usize>, agents: usize) -> (usize, Vec<Opened>) {
+    fn pick(
+        sets: &[(&Opened, usize)],
+        from: usize,
+        agents: usize,
+        chosen: &mut Vec<usize>,
+        union: &mut Opened,
+        pressure: usize,
+        res: &mut (usize, Vec<usize>),
+    ) {
+        if pressure > res.0 {
+            *res = (pressure, chosen.clone());
         }
+        if agents == 0 {
+            return;
+        }
+        for (i, (set, set_pressure)) in sets.iter().enumerate().skip(from) {
+            if pressure + set_pressure * agents <= res.0 {
+                break;
+            }
+            if !set.overlaps(union) {
+                // the sets chosen never overlap, so toggling adds and removes exactly this one
+                chosen.push(i);
+                union.toggle(set);
+                pick(sets, i, agents - 1, chosen, union, pressure + set_pressure, res);
+                union.toggle(set);
+                chosen.pop();
+            }
+        }
+    }
 
-        options.append(
-            &mut leads_to.iter()
-                .map(|location| {
-                    recurse_p1(
-                        cache,
-                        opened.clone(),
-                        valves,
-                        location.clone(),
-                        time - 1,
-                    )
-                })
-                .collect::<Vec<usize>>()
-        );
-
-        options.iter().max().cloned().unwrap_or(0usize)
-    };
-
-    cache.insert(key, res);
-    res
+    // the most any subset of a set releases, smallest sets first. every subset of a set
+    // one agent can open in time can be opened in time too, so the sets one valve smaller
+    // are all in `best`. a set is never needed when one of them releases at least as much
+    let mut by_size: Vec<(&Opened, usize)> = best.iter().map(|(set, pressure)| (set, *pressure)).collect();
+    by_size.sort_by_key(|(set, _)| set.count());
+    let mut within: HashMap<Opened, usize> = HashMap::new();
+    let mut sets: Vec<(&Opened, usize)> = vec![];
+    for (set, pressure) in by_size {
+        let smaller = set.open_valves()
+            .into_iter()
+            .map(|valve| {
+                let mut subset = set.clone();
+                subset.close(valve);
+                within.get(&subset).copied().unwrap_or(0)
+            })
+            .max()
+            .unwrap_or(0);
+        within.insert(set.clone(), smaller.max(pressure));
+        if pressure > smaller {
+            sets.push((set, pressure));
+        }
+    }
+    sets.sort_by_key(|(set, pressure)| (Reverse(*pressure), *set));
+
+    let size = best.keys().next().map_or(0, |set| set.size);
+    let mut res = (0, vec![]);
+    pick(&sets, 0, agents, &mut vec![], &mut Opened::new(size), 0, &mut res);
+    let mut team: Vec<Opened> = res.1.into_iter().map(|i| sets[i].0.clone()).collect();
+    // whoever isn't needed stays put
+    team.resize(agents, Opened::new(size));
+    (res.0, team)
 }
 
-pub fn solve_p1(lines: Vec<String>) -> u32 {
+// the most pressure `agents` working together from `start` can release in `time` minutes
+pub fn max_pressure(lines: Vec<String>, start: &str, agents: usize, time: usize) -> usize {
     let (keys_map, valves) = parse_lines(lines);
-
-    let mut cache = HashMap::<(usize, u64, u64), usize>::new();
-    recurse_p1(&mut cache, 0, &valves, keys_map.get("AA").unwrap().to_owned(), 30) as u32
+    let network = Network::new(&keys_map, &valves, start);
+    best_team(&best_per_subset(&network, time), agents).0
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Move {
     Open(usize),
-    To(u64),
+    To(usize),
+    Wait,
 }
 
 impl Move {
-    fn pressure(&self) -> usize {
-        match self {
-            Self::Open(pressure) => *pressure,
-            _ => 0,
+    fn open(&self, opened: &mut Opened) {
+        if let Self::Open(key) = self {
+            opened.open(*key)
+        }
+    }
+}
+
+// the valves walked through from one valve to another, by breadth first search
+fn route(valves: &Valves, from: usize, to: usize) -> Vec<usize> {
+    let mut came_from = HashMap::from([(from, from)]);
+    let mut to_check = VecDeque::from([from]);
+    while let Some(pos) = to_check.pop_front() {
+        for next in valves[pos].1.iter() {
+            if !came_from.contains_key(next) {
+                came_from.insert(*next, pos);
+                to_check.push_back(*next);
+            }
         }
     }
 
-    fn open(&self, opened: &mut Opened, pos: u64) {
-        match self {
-            Self::Open(_) => opened.open(pos),
-            _ => {},
+    let mut res = vec![];
+    let mut pos = to;
+    while pos != from {
+        res.push(pos);
+        pos = came_from[&pos];
+    }
+    res.reverse();
+    res
+}
+
+// an order of opening exactly the valves in `set` that releases the most pressure
+fn best_order(network: &Network, set: &Opened, time: usize) -> Vec<usize> {
+    fn visit(
+        network: &Network,
+        set: &Opened,
+        pos: Option<usize>,
+        time_left: usize,
+        order: &mut Vec<usize>,
+        pressure: usize,
+        best: &mut (usize, Vec<usize>),
+    ) {
+        let mut opened = Opened::new(set.size);
+        order.iter().for_each(|i| opened.open(*i));
+        if opened == *set && pressure > best.0 {
+            *best = (pressure, order.clone());
+        }
+
+        for next in opened.still_closed().into_iter().filter(|i| !set.is_closed(*i)) {
+            let walk = match pos {
+                None => network.from_start[next],
+                Some(pos) => network.dist[pos][next],
+            };
+            if walk + 1 < time_left {
+                let left = time_left - walk - 1;
+                order.push(next);
+                visit(network, set, Some(next), left, order, pressure + network.rates[next] * left, best);
+                order.pop();
+            }
         }
     }
+
+    let mut best = (0, vec![]);
+    visit(network, set, None, time, &mut vec![], 0, &mut best);
+    best.1
 }
 
+// every agent's moves minute by minute
 #[derive(Debug, Clone)]
+pub struct Plan {
+    moves: Vec<Vec<Move>>,
+    // pressure released during each minute
+    released: Vec<usize>,
+    names: Vec<String>,
+}
+
+impl Plan {
+    fn total(&self) -> usize {
+        self.released.iter().sum()
+    }
+
+    // pressure released by the end of each minute
+    fn curve(&self) -> Vec<usize> {
+        self.released.iter()
+            .scan(0, |total, released| {
+                *total += released;
+                Some(*total)
+            })
+            .collect()
+    }
+
+    // the puzzle's narrative, you and then the elephants
+    fn timeline(&self) -> String {
+        let subject = |agent: usize| match (agent, self.moves.len()) {
+            (0, _) => ("You", ""),
+            (1, 2) => ("The elephant", "s"),
+            _ => ("An elephant", "s"),
+        };
+        let mut opened = Opened::new(self.names.len());
+        let mut res = vec![];
+
+        for (minute, released) in self.released.iter().enumerate() {
+            res.push(format!("== Minute {} ==", minute + 1));
+
+            let mut open: Vec<&String> = self.names.iter()
+                .enumerate()
+                .filter(|(key, _)| !opened.is_closed(*key))
+                .map(|(_, name)| name)
+                .collect();
+            open.sort();
+            res.push(match open.as_slice() {
+                [] => "No valves are open.".to_string(),
+                [one] => format!("Valve {} is open, releasing {} pressure.", one, released),
+                [first, second] => format!("Valves {} and {} are open, releasing {} pressure.", first, second, released),
+                [rest @ .., last] => format!(
+                    "Valves {}, and {} are open, releasing {} pressure.",
+                    rest.iter().map(|n| n.as_str()).collect::<Vec<&str>>().join(", "),
+                    last,
+                    released,
+                ),
+            });
+
+            for (agent, moves) in self.moves.iter().enumerate() {
+                let (who, s) = subject(agent);
+                match moves[minute] {
+                    Move::To(key) => res.push(format!("{} move{} to valve {}.", who, s, self.names[key])),
+                    Move::Open(key) => res.push(format!("{} open{} valve {}.", who, s, self.names[key])),
+                    Move::Wait => {},
+                }
+                moves[minute].open(&mut opened);
+            }
+            res.push("".to_string());
+        }
+        res.join("\n")
+    }
+}
+
+// the moves behind max_pressure
+pub fn best_plan(lines: Vec<String>, start: &str, agents: usize, time: usize) -> Plan {
+    let (keys_map, valves) = parse_lines(lines);
+    let network = Network::new(&keys_map, &valves, start);
+    let (_, sets) = best_team(&best_per_subset(&network, time), agents);
+
+    let moves: Vec<Vec<Move>> = sets.iter()
+        .map(|set| {
+            let mut res = vec![];
+            let mut pos = keys_map[start];
+            for next in best_order(&network, set, time) {
+                let key = network.keys[next];
+                res.extend(route(&valves, pos, key).into_iter().map(Move::To));
+                res.push(Move::Open(key));
+                pos = key;
+            }
+            res.resize(time, Move::Wait);
+            res
+        })
+        .collect();
+
+    let mut opened = Opened::new(keys_map.len());
+    let released = (0..time)
+        .map(|minute| {
+            let released = valves.iter()
+                .enumerate()
+                .filter(|(key, _)| !opened.is_closed(*key))
+                .map(|(_, (rate, _))| rate)
+                .sum();
+            moves.iter().for_each(|m| m[minute].open(&mut opened));
+            released
+        })
+        .collect();
+
+    let mut names = vec![String::new(); keys_map.len()];
+    keys_map.into_iter().for_each(|(name, key)| names[key] = name);
+    Plan { moves, released, names }
+}
+
+// which valves are open, one bit per valve in as many words as it takes
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 struct Opened {
-    val: u64,
+    words: Vec<u64>,
     size: usize,
 }
 
 impl Opened {
     fn new(size: usize) -> Opened {
         Opened{
-            val: 0,
+            words: vec![0; size.div_ceil(64)],
             size,
         }
     }
 
-    fn still_closed(&self) -> Vec<u64> {
-        let mut res = Vec::with_capacity(self.size);
-        for i in 0..(self.size as u64) {
-            if self.val & (1 << i) == 0 {
-                res.push(1 << i);
-            }
-        }
-        res
+    fn still_closed(&self) -> Vec<usize> {
+        (0..self.size).filter(|valve| self.is_closed(*valve)).collect()
     }
 
-    fn is_closed(&self, key: u64) -> bool {
-        self.val & key == 0
+    fn open_valves(&self) -> Vec<usize> {
+        (0..self.size).filter(|valve| !self.is_closed(*valve)).collect()
     }
 
-    fn open(&mut self, key: u64) {
-        self.val |= key
+    fn count(&self) -> u32 {
+        self.words.iter().map(|w| w.count_ones()).sum()
     }
 
-    fn key(&self) -> u64 {
-        self.val
+    fn is_closed(&self, valve: usize) -> bool {
+        self.words[valve / 64] & (1 << (valve % 64)) == 0
     }
 
-    fn show(&self) -> String {
-        format!("{:b}", self.val)
+    fn open(&mut self, valve: usize) {
+        self.words[valve / 64] |= 1 << (valve % 64)
     }
-}
 
-fn recurse_p2(
-    cache: &mut HashMap<(usize, u64, u64), usize>,
-    opened: Opened,
-    valves: &HashMap::<u64, (usize, Vec<u64>)>,
-    pos_you: u64,
-    from_you: u64,
-    pos_elephant: u64,
-    from_elephant: u64,
-    time: usize,
-    pressure_released: usize,
-    most_pressure_released: usize,
-) -> usize {
-    // base case
-    if time == 0 {
-        return 0
-    }
-
-    // check cache
-    let key = (time, pos_you | pos_elephant, opened.key());
-    if let Some(&pressure) = cache.get(&key) {
-        return pressure
-    }
-
-    // ensure it's even possible to beat the best known so far
-    let potential: usize = opened.still_closed()
-        .iter()
-        .map(|key| {
-            match valves.get(key) {
-                Some((rate, _)) => (time - 1) * rate,
-                None => 0,
-            }
-        }).sum();
-
-    if time > 2 && pressure_released + potential < most_pressure_released {
-        return 0
+    fn close(&mut self, valve: usize) {
+        self.words[valve / 64] &= !(1 << (valve % 64))
     }
 
-    // recurse through all options
-    let res = {
-        let mut moves_you: Vec<Move> = Vec::new();
-        let mut already_opened = false;
-        let (rate_you, leads_to_you) = valves.get(&pos_you).unwrap();
-        if *rate_you > 0 && opened.is_closed(pos_you) {
-            moves_you.push(Move::Open((time - 1) * rate_you));
-            already_opened = true;
-        }
-        for new_pos in leads_to_you {
-            if *new_pos != from_you {
-                moves_you.push(Move::To(*new_pos))
-            }
-        }
-
-        let mut moves_elephant: Vec<Move> = Vec::new();
-        let (rate_elephant, leads_to_elephant) = valves.get(&pos_elephant).unwrap();
-        if !((pos_you == pos_elephant) && already_opened) && *rate_elephant > 0 && opened.is_closed(pos_elephant) {
-            moves_elephant.push(Move::Open((time - 1) * rate_elephant));
-        }
-        for new_pos in leads_to_elephant {
-            if *new_pos != from_elephant {
-                moves_elephant.push(Move::To(*new_pos))
-            }
-        }
-
-        let mut options = Vec::<usize>::new();
-        let mut best_known = most_pressure_released;
-        for move_you in &moves_you {
-            for move_elephant in &moves_elephant {
-                let pos_you_new = match move_you {
-                    Move::Open(_) => pos_you,
-                    Move::To(new_pos) => *new_pos,
-                };
-                let pos_elephant_new = match move_elephant {
-                    Move::Open(_) => pos_elephant,
-                    Move::To(new_pos) => *new_pos,
-                };
-
-                let pressure = move_you.pressure() + move_elephant.pressure();
-                let mut new_opened = opened.clone();
-                move_you.open(&mut new_opened, pos_you);
-                move_elephant.open(&mut new_opened, pos_elephant);
-
-                let sub_res = pressure + recurse_p2(
-                    cache,
-                    new_opened,
-                    valves,
-                    pos_you_new,
-                    pos_you,
-                    pos_elephant_new,
-                    pos_elephant,
-                    time - 1,
-                    pressure_released + pressure,
-                    best_known,
-                );
-
-                if sub_res > best_known {
-                    best_known = sub_res;
-                }
-
-                options.push(sub_res);
-            }
-        }
+    // opens the valves open in `other` that are closed here, and closes the ones open in both
+    fn toggle(&mut self, other: &Opened) {
+        self.words.iter_mut().zip(other.words.iter()).for_each(|(a, b)| *a ^= b);
+    }
 
-        options.into_iter().max().unwrap_or(0)
-    };
+    // whether any valve is open in both
+    fn overlaps(&self, other: &Opened) -> bool {
+        self.words.iter().zip(other.words.iter()).any(|(a, b)| a & b != 0)
+    }
+}
 
-    // save result
-    cache.insert(key, res);
-    res
+pub fn solve_p1(lines: Vec<String>) -> u32 {
+    max_pressure(lines, "AA", 1, 30) as u32
 }
 
 pub fn solve_p2(lines: Vec<String>) -> u32 {
-    let (keys_map, valves) = parse_lines(lines);
+    max_pressure(lines, "AA", 2, 26) as u32
+}
 
-    let opened = Opened::new(keys_map.len());
-    let pos_init = *keys_map.get("AA").unwrap();
-    let mut cache = HashMap::<(usize, u64, u64), usize>::new();
-
-    recurse_p2(
-        &mut cache,
-        opened,
-        &valves,
-        pos_init,
-        pos_init,
-        pos_init,
-        pos_init,
-        26,
-        0,
-        0,
-    ) as u32
+// the most pressure a team can release:
+//   <agents> <minutes> [--start VALVE] [--plan] [--curve]
+// --plan tells the minute by minute story, --curve lists the pressure released so far
+pub fn run_query(lines: Vec<String>, args: &[String]) -> Result<String, String> {
+    let number = |i: usize, what: &str| -> Result<usize, String> {
+        let arg = args.get(i).ok_or(format!("missing {}", what))?;
+        arg.parse::<usize>().map_err(|_| format!("bad {}: {:?}", what, arg))
+    };
+    let agents = number(0, "agent count")?;
+    let time = number(1, "minutes")?;
+
+    let mut start = "AA";
+    let mut show_plan = false;
+    let mut show_curve = false;
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--start" => {
+                i += 1;
+                start = args.get(i).ok_or("missing start valve")?;
+            },
+            "--plan" => show_plan = true,
+            "--curve" => show_curve = true,
+            other => return Err(format!("unknown argument: {:?}", other)),
+        }
+        i += 1;
+    }
+    let lines: Vec<String> = lines.into_iter().filter(|l| !l.is_empty()).collect();
+    if !parse_lines(lines.clone()).0.contains_key(start) {
+        return Err(format!("there is no valve {}", start));
+    }
+
+    let plan = best_plan(lines, start, agents, time);
+    let mut res = String::new();
+    if show_plan {
+        res.push_str(&plan.timeline());
+        res.push('\n');
+    }
+    if show_curve {
+        res.push_str("minute,released,total\n");
+        for (minute, (released, total)) in plan.released.iter().zip(plan.curve()).enumerate() {
+            res.push_str(&format!("{},{},{}\n", minute + 1, released, total));
+        }
+    }
+    res.push_str(&format!("{}\n", plan.total()));
+    Ok(res)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::day16::day16::{contains, Opened, solve_p1, solve_p2};
+    use std::collections::HashMap;
+    use crate::day16::day16::{best_per_subset, best_plan, best_team, max_pressure, Move, parse_lines, Network, Opened, Valves, solve_p1, solve_p2};
+    use crate::lcg::Lcg;
+
+    fn example() -> Vec<String> {
+        vec![
+            "Valve AA has flow rate=0; tunnels lead to valves DD, II, BB".to_owned(),
+            "Valve BB has flow rate=13; tunnels lead to valves CC, AA".to_owned(),
+            "Valve CC has flow rate=2; tunnels lead to valves DD, BB".to_owned(),
+            "Valve DD has flow rate=20; tunnels lead to valves CC, AA, EE".to_owned(),
+            "Valve EE has flow rate=3; tunnels lead to valves FF, DD".to_owned(),
+            "Valve FF has flow rate=0; tunnels lead to valves EE, GG".to_owned(),
+            "Valve GG has flow rate=0; tunnels lead to valves FF, HH".to_owned(),
+            "Valve HH has flow rate=22; tunnel leads to valve GG".to_owned(),
+            "Valve II has flow rate=0; tunnels lead to valves AA, JJ".to_owned(),
+            "Valve JJ has flow rate=21; tunnel leads to valve II".to_owned(),
+        ]
+    }
 
     #[test]
     fn test_opened() {
         let mut opened = Opened::new(32);
 
         for i in 0..32 {
-            assert_eq!(opened.is_closed(i << 1), true);
-            assert_eq!(!contains(opened.val, i << 1), true);
+            assert!(opened.is_closed(i));
         }
-        assert_eq!(opened.still_closed(), (0u64..32).map(|k| 1 << k).collect::<Vec<u64>>());
+        assert_eq!(opened.still_closed(), (0..32).collect::<Vec<usize>>());
         for i in 0..16 {
-            let key = 1 << i;
-            opened.open(key);
-            assert_eq!(opened.is_closed(key), false);
-            assert_eq!(opened.is_closed(key << 16), true);
-            assert_eq!(!contains(opened.val, key), false);
-            assert_eq!(!contains(opened.val, key << 16), true);
+            opened.open(i);
+            assert!(!opened.is_closed(i));
+            assert!(opened.is_closed(i + 16));
         }
         for i in 16..30 {
-            let key = 1 << i;
-            opened.open(key);
-            assert_eq!(opened.is_closed(key), false);
+            opened.open(i);
+            assert!(!opened.is_closed(i));
         }
-        assert_eq!(opened.still_closed(), Vec::from([1 << 30, 1 << 31]));
+        assert_eq!(opened.still_closed(), Vec::from([30, 31]));
+    }
+
+    #[test]
+    fn test_opened_past_64() {
+        let mut opened = Opened::new(200);
+        let mut other = Opened::new(200);
+        assert_eq!(opened.still_closed().len(), 200);
+
+        for i in [0, 63, 64, 127, 128, 199] {
+            opened.open(i);
+            assert!(!opened.is_closed(i));
+        }
+        assert!(opened.is_closed(65));
+        assert_eq!(opened.still_closed().len(), 194);
+
+        // bits in different words don't collide
+        other.open(1);
+        other.open(65);
+        other.open(129);
+        assert!(!opened.overlaps(&other));
+        other.open(199);
+        assert!(opened.overlaps(&other));
+        assert_eq!(opened.count(), 6);
+        assert_eq!(opened.open_valves(), vec![0, 63, 64, 127, 128, 199]);
+
+        // toggling a set that doesn't overlap adds it, and toggling it again takes it away
+        let mut union = opened.clone();
+        other.close(199);
+        union.toggle(&other);
+        assert_eq!(union.count(), 9);
+        union.toggle(&other);
+        assert_eq!(union, opened);
+
+        opened.close(64);
+        assert!(opened.is_closed(64));
+        assert!(!opened.is_closed(63));
     }
 
     #[test]
     fn test_solve_p1() {
-        let lines: Vec<String> = vec![
-            "Valve AA has flow rate=0; tunnels lead to valves DD, II, BB".to_owned(),
-            "Valve BB has flow rate=13; tunnels lead to valves CC, AA".to_owned(),
-            "Valve CC has flow rate=2; tunnels lead to valves DD, BB".to_owned(),
-            "Valve DD has flow rate=20; tunnels lead to valves CC, AA, EE".to_owned(),
-            "Valve EE has flow rate=3; tunnels lead to valves FF, DD".to_owned(),
-            "Valve FF has flow rate=0; tunnels lead to valves EE, GG".to_owned(),
-            "Valve GG has flow rate=0; tunnels lead to valves FF, HH".to_owned(),
-            "Valve HH has flow rate=22; tunnel leads to valve GG".to_owned(),
-            "Valve II has flow rate=0; tunnels lead to valves AA, JJ".to_owned(),
-            "Valve JJ has flow rate=21; tunnel leads to valve II".to_owned(),
-        ];
-        assert_eq!(solve_p1(lines), 1651)
+        assert_eq!(solve_p1(example()), 1651)
     }
 
     #[test]
     fn test_solve_p2() {
+        assert_eq!(solve_p2(example()), 1707)
+    }
+
+    // a connected network of `size` valves named AA, AB, ..., about half of them with flow
+    fn generate(rng: &mut Lcg, size: usize) -> Vec<String> {
+        let mut next = |n: usize| rng.below(n);
+        let name = |i: usize| format!("{}{}", (b'A' + (i / 26) as u8) as char, (b'A' + (i % 26) as u8) as char);
+
+        let mut tunnels: Vec<Vec<usize>> = vec![vec![]; size];
+        for i in 1..size {
+            let j = next(i);
+            tunnels[i].push(j);
+            tunnels[j].push(i);
+        }
+        for _ in 0..size / 2 {
+            let (i, j) = (next(size), next(size));
+            if i != j && !tunnels[i].contains(&j) {
+                tunnels[i].push(j);
+                tunnels[j].push(i);
+            }
+        }
+
+        (0..size)
+            .map(|i| {
+                let rate = if next(2) == 0 { 0 } else { next(25) + 1 };
+                let leads_to: Vec<String> = tunnels[i].iter().map(|j| name(*j)).collect();
+                format!("Valve {} has flow rate={}; tunnels lead to valves {}", name(i), rate, leads_to.join(", "))
+            })
+            .collect()
+    }
+
+    // minute by minute over the whole tunnel graph, every agent either opening its valve
+    // or walking on, memoized on the complete state
+    fn brute_force(lines: Vec<String>, agents: usize, time: usize) -> usize {
+        fn go(
+            cache: &mut HashMap<(usize, Vec<usize>, Opened), usize>,
+            valves: &Valves,
+            positions: Vec<usize>,
+            opened: Opened,
+            time: usize,
+        ) -> usize {
+            if time == 0 {
+                return 0;
+            }
+            let mut sorted = positions.clone();
+            sorted.sort();
+            let key = (time, sorted, opened.clone());
+            if let Some(res) = cache.get(&key) {
+                return *res;
+            }
+
+            // every combination of the agents' moves, opening as Some(pressure)
+            let mut combos: Vec<(Vec<usize>, Opened, usize)> = vec![(vec![], opened, 0)];
+            for pos in positions.iter() {
+                let (rate, leads_to) = &valves[*pos];
+                let mut extended = vec![];
+                for (moved, opened, pressure) in combos {
+                    if *rate > 0 && opened.is_closed(*pos) {
+                        let mut moved = moved.clone();
+                        moved.push(*pos);
+                        let mut opened = opened.clone();
+                        opened.open(*pos);
+                        extended.push((moved, opened, pressure + rate * (time - 1)));
+                    }
+                    for next in leads_to {
+                        let mut moved = moved.clone();
+                        moved.push(*next);
+                        extended.push((moved, opened.clone(), pressure));
+                    }
+                }
+                combos = extended;
+            }
+
+            let res = combos.into_iter()
+                .map(|(moved, opened, pressure)| pressure + go(cache, valves, moved, opened, time - 1))
+                .max()
+                .unwrap();
+            cache.insert(key, res);
+            res
+        }
+
+        let (keys_map, valves) = parse_lines(lines);
+        let opened = Opened::new(valves.len());
+        go(&mut HashMap::new(), &valves, vec![keys_map["AA"]; agents], opened, time)
+    }
+
+    #[test]
+    fn test_network() {
+        let (keys_map, valves) = parse_lines(example());
+        let network = Network::new(&keys_map, &valves, "AA");
+
+        assert_eq!(network.keys, vec![1, 2, 3, 4, 7, 9]);
+        assert_eq!(network.rates, vec![13, 2, 20, 3, 22, 21]);
+        assert_eq!(network.from_start, vec![1, 2, 1, 2, 5, 2]);
+        assert_eq!(network.dist[4], vec![6, 5, 4, 3, 0, 7]);
+
+        // the puzzle's route opens DD, BB, JJ, HH, EE and CC
+        let best = best_per_subset(&network, 30);
+        let mut all = Opened::new(6);
+        (0..6).for_each(|i| all.open(i));
+        assert_eq!(best[&all], 1651);
+        assert_eq!(best_team(&best_per_subset(&network, 26), 2).0, 1707);
+
+        // more agents than valves leaves some of them idle
+        let best = best_per_subset(&network, 26);
+        assert!(best_team(&best, 3).0 > 1707);
+        assert_eq!(best_team(&best, 6).0, best_team(&best, 10).0);
+        assert_eq!(best_team(&best, 0), (0, vec![]));
+    }
+
+    #[test]
+    fn test_matches_brute_force() {
+        let mut rng = Lcg::new(16);
+        for size in [2, 4, 6, 8] {
+            for _ in 0..2 {
+                let lines = generate(&mut rng, size);
+                let (keys_map, valves) = parse_lines(lines.clone());
+                let network = Network::new(&keys_map, &valves, "AA");
+
+                let one = best_per_subset(&network, 12).into_values().max().unwrap();
+                assert_eq!(one, brute_force(lines.clone(), 1, 12), "{:?}", lines);
+                let two = best_team(&best_per_subset(&network, 8), 2).0;
+                assert_eq!(two, brute_force(lines.clone(), 2, 8), "{:?}", lines);
+                if size <= 6 {
+                    let three = best_team(&best_per_subset(&network, 6), 3).0;
+                    assert_eq!(three, brute_force(lines.clone(), 3, 6), "{:?}", lines);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_past_64_valves() {
+        let lines = generate(&mut Lcg::new(50), 200);
+        let (keys_map, valves) = parse_lines(lines.clone());
+        assert_eq!(valves.len(), 200);
+        let network = Network::new(&keys_map, &valves, "AA");
+        assert!(network.rates.len() > 64);
+
+        let one = max_pressure(lines.clone(), "AA", 1, 8);
+        assert_eq!(one, brute_force(lines.clone(), 1, 8));
+        assert_eq!(best_plan(lines.clone(), "AA", 1, 8).total(), one);
+        let two = max_pressure(lines.clone(), "AA", 2, 4);
+        assert_eq!(two, brute_force(lines.clone(), 2, 4));
+        assert_eq!(best_plan(lines.clone(), "AA", 2, 4).total(), two);
+    }
+
+    #[test]
+    fn test_start() {
         let lines: Vec<String> = vec![
-            "Valve AA has flow rate=0; tunnels lead to valves DD, II, BB".to_owned(),
-            "Valve BB has flow rate=13; tunnels lead to valves CC, AA".to_owned(),
-            "Valve CC has flow rate=2; tunnels lead to valves DD, BB".to_owned(),
-            "Valve DD has flow rate=20; tunnels lead to valves CC, AA, EE".to_owned(),
-            "Valve EE has flow rate=3; tunnels lead to valves FF, DD".to_owned(),
-            "Valve FF has flow rate=0; tunnels lead to valves EE, GG".to_owned(),
-            "Valve GG has flow rate=0; tunnels lead to valves FF, HH".to_owned(),
-            "Valve HH has flow rate=22; tunnel leads to valve GG".to_owned(),
-            "Valve II has flow rate=0; tunnels lead to valves AA, JJ".to_owned(),
-            "Valve JJ has flow rate=21; tunnel leads to valve II".to_owned(),
+            "Valve AA has flow rate=0; tunnels lead to valves BB".to_owned(),
+            "Valve BB has flow rate=10; tunnels lead to valves AA, CC".to_owned(),
+            "Valve CC has flow rate=1; tunnels lead to valves BB".to_owned(),
         ];
-        assert_eq!(solve_p2(lines), 1707)
+        // from BB its own valve opens first, and CC a minute's walk later
+        assert_eq!(max_pressure(lines.clone(), "BB", 1, 5), 10 * 4 + 2);
+        assert_eq!(max_pressure(lines.clone(), "AA", 1, 5), 10 * 3 + 1);
+        assert_eq!(max_pressure(lines, "BB", 2, 5), 10 * 4 + 3);
+    }
+
+    #[test]
+    fn test_timeline() {
+        let plan = best_plan(example(), "AA", 1, 30);
+        assert_eq!(plan.total(), 1651);
+        assert_eq!(plan.curve().last(), Some(&1651));
+        assert_eq!(plan.curve()[..4], [0, 0, 20, 40]);
+
+        // the same route the puzzle walks through
+        let timeline = plan.timeline();
+        let minutes: Vec<&str> = timeline.split("\n\n").collect();
+        assert_eq!(minutes.len(), 30);
+        assert_eq!(minutes[0], "== Minute 1 ==\nNo valves are open.\nYou move to valve DD.");
+        assert_eq!(minutes[1], "== Minute 2 ==\nNo valves are open.\nYou open valve DD.");
+        assert_eq!(minutes[4], "== Minute 5 ==\nValve DD is open, releasing 20 pressure.\nYou open valve BB.");
+        assert_eq!(minutes[5], "== Minute 6 ==\nValves BB and DD are open, releasing 33 pressure.\nYou move to valve AA.");
+        assert_eq!(minutes[20], "== Minute 21 ==\nValves BB, DD, HH, and JJ are open, releasing 76 pressure.\nYou open valve EE.");
+        assert_eq!(minutes[29], "== Minute 30 ==\nValves BB, CC, DD, EE, HH, and JJ are open, releasing 81 pressure.\n");
     }
-}
 
+    #[test]
+    fn test_team_plan() {
+        let plan = best_plan(example(), "AA", 2, 26);
+        assert_eq!(plan.total(), 1707);
+        assert_eq!(plan.moves.len(), 2);
+
+        // every move follows a tunnel, and no valve is opened twice
+        let (keys_map, valves) = parse_lines(example());
+        let mut opened = vec![];
+        for moves in plan.moves.iter() {
+            assert_eq!(moves.len(), 26);
+            let mut pos = keys_map["AA"];
+            for m in moves {
+                match m {
+                    Move::To(key) => {
+                        assert!(valves[pos].1.contains(key));
+                        pos = *key;
+                    },
+                    Move::Open(key) => {
+                        assert_eq!(*key, pos);
+                        assert!(!opened.contains(key));
+                        opened.push(*key);
+                    },
+                    Move::Wait => {},
+                }
+            }
+        }
+        assert_eq!(opened.len(), 6);
+
+        let timeline = plan.timeline();
+        assert!(timeline.starts_with("== Minute 1 ==\nNo valves are open.\nYou move to valve "));
+        assert!(timeline.contains("The elephant opens valve "));
+        assert!(best_plan(example(), "AA", 3, 26).timeline().contains("An elephant "));
+    }
+}
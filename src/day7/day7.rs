@@ -1,274 +1,881 @@
-use std::fmt;
-use regex::Regex;
-
-#[derive(Debug, Clone)]
-struct Path(Vec<String>);
-
-impl Path {
-    fn new() -> Path {
-        Path(vec![])
-    }
-
-    fn root() -> Path {
-        Path(vec![])
-    }
-
-    fn is_empty(&self) -> bool {
-        return self.0.len() == 0
-    }
-
-    fn push(&self, segment: String) -> Path {
-        let mut res = self.clone();
-        res.0.push(segment);
-        res
-    }
-
-    fn pop(&self) -> Path {
-        let mut res = self.clone();
-        res.0.pop();
-        res
-    }
-
-    fn drop(&self, n: usize) -> Path {
-        Path(self.0.iter().skip(n).cloned().collect())
-    }
-}
-
-// Implement `Display` for `MinMax`.
-impl fmt::Display for Path {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let res = self.0.iter().fold("".to_string(), |mut accum, segment| {
-            accum.push_str("/");
-            accum.push_str(segment);
-            accum
-        });
-
-        match res.as_ref() {
-            "" => write!(f, "/"),
-            path => write!(f, "{}", path)
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-struct Folder {
-    name: String,
-
-    files: Vec<(String, usize)>,
-    folders: Vec<Folder>,
-}
-
-impl Folder {
-    fn root() -> Folder {
-        Folder{
-            name: "R".to_string(),
-            files: vec![],
-            folders: vec![]
-        }    
-    }
-
-    fn size(&self) -> usize {
-        self.files.iter().map(|(_, size)| size ).sum::<usize>() + self.folders.iter().map(|f| f.size() ).sum::<usize>()
-    }
-    
-    fn add_file(&self, location: &Path, name: String, size: usize) -> Folder{
-        let mut res = self.clone();
-
-        if location.is_empty() {
-            res.files.push((name, size));
-        } else {
-            res.folders = res.folders.into_iter().map(|folder| {
-                if folder.name == *location.0.first().unwrap() {
-                    folder.add_file(&location.drop(1), name.clone(), size)
-                } else {
-                    folder
-                }
-            }).collect()
-        }
-
-        res
-    }
-
-    fn add_folder(&self, location: &Path, name: String) -> Folder {
-        let mut res = self.clone();
-
-        if location.is_empty() {
-            res.folders.push(Folder{
-                name,
-                files: vec![],
-                folders: vec![]
-            });
-        } else {
-            res.folders = res.folders.into_iter().map(|folder| {
-                if folder.name == *location.0.first().unwrap() {
-                    folder.add_folder(&location.drop(1), name.clone())
-                } else {
-                    folder
-                }
-            }).collect()
-        }
-
-        res
-    }
-
-    fn all_folders(&self) -> Vec<Folder> {
-        let mut res = vec![self.clone()];
-        for folder in &self.folders {
-            res.append(&mut folder.all_folders())
-        }
-        res
-    }
-
-    fn display(&self, location: Path) {
-        println!("{}, size={}", location, self.size());
-
-        for folder in &self.folders {
-            let new_loc = location.push(folder.name.clone());
-            println!("{}, size={}", new_loc, folder.size());
-            folder.display(new_loc)
-        }
-        for (name, size) in &self.files {
-            println!("{}/{}, size={}", location, name, size)
-        }
-    }
-}
-
-fn build_fs(lines: Vec<String>) -> Folder {
-    let cd_re = Regex::new(r"\$ cd (.+)").unwrap();
-    let ls_re = Regex::new(r"\$ ls").unwrap();
-    let disp_dir = Regex::new(r"dir (\w+)").unwrap();
-    let disp_file = Regex::new(r"(\d+) (.*)").unwrap();
-
-    let mut path = Path::new();
-    let mut root = Folder::root();
-
-    for line in lines.into_iter() {
-        if let Some(captures) = cd_re.captures(&line) {
-            let folder = &captures[1];
-            // println!("cd to directory={}", folder);
-            path = match folder {
-                "/" => Path::root(),
-                ".." => path.pop(),
-                location => path.push(location.to_string())
-            };
-            // println!("\t\tnew path={}", path);
-
-        } else if let Some(_) = ls_re.captures(&line) {
-            // println!("ls command");
-
-        } else if let Some(captures) = disp_dir.captures(&line) {
-            let name = &captures[1];
-            // println!("displaying folder name={}", name);
-
-            root = root.add_folder(&path, name.to_string());
-
-        } else if let Some(captures) = disp_file.captures(&line) {
-            let size = &captures[1].parse::<usize>().unwrap();
-            let name = &captures[2];
-            // println!("displaying file name={}, size={}", name, size);
-
-            root = root.add_file(&path, name.to_string(), *size);
-
-        } else {
-            println!("line didn't match anything!!!: {:?}", line)
-        }
-    }
-
-    root
-}
-
-pub fn solve_p1(lines: Vec<String>) -> u32 {
-    let root = build_fs(lines);
-    root.all_folders()
-        .into_iter()
-        .map(|f| f.size() )
-        .filter(|s| *s <= 100000 )
-        .sum::<usize>() as u32
-}
-
-pub fn solve_p2(lines: Vec<String>) -> u32 {
-    let root = build_fs(lines);
-
-    let disk_space: usize = 70000000;
-    let update = 30000000;
-
-    let total_size = root.size();
-    let unused = disk_space - total_size;
-    let needed = update - unused;
-
-    let mut large_enough = root.all_folders()
-        .into_iter()
-        .map(|f| f.size() )
-        .filter(|s| *s >= needed )
-        .collect::<Vec<usize>>();
-
-    large_enough.sort();
-
-    *large_enough.first().unwrap() as u32
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::{solve_p1, solve_p2};
-
-    #[test]
-    fn test_solve_p1() {
-        let lines: Vec<String> = vec![
-            "$ cd /".to_string(),
-            "$ ls".to_string(),
-            "dir a".to_string(),
-            "14848514 b.txt".to_string(),
-            "8504156 c.dat".to_string(),
-            "dir d".to_string(),
-            "$ cd a".to_string(),
-            "$ ls".to_string(),
-            "dir e".to_string(),
-            "29116 f".to_string(),
-            "2557 g".to_string(),
-            "62596 h.lst".to_string(),
-            "$ cd e".to_string(),
-            "$ ls".to_string(),
-            "584 i".to_string(),
-            "$ cd ..".to_string(),
-            "$ cd ..".to_string(),
-            "$ cd d".to_string(),
-            "$ ls".to_string(),
-            "4060174 j".to_string(),
-            "8033020 d.log".to_string(),
-            "5626152 d.ext".to_string(),
-            "7214296 k".to_string(),
-        ];
-        assert_eq!(solve_p1(lines), 95437)
-    }
-
-    #[test]
-    fn test_solve_p2() {
-        let lines: Vec<String> = vec![
-            "$ cd /".to_string(),
-            "$ ls".to_string(),
-            "dir a".to_string(),
-            "14848514 b.txt".to_string(),
-            "8504156 c.dat".to_string(),
-            "dir d".to_string(),
-            "$ cd a".to_string(),
-            "$ ls".to_string(),
-            "dir e".to_string(),
-            "29116 f".to_string(),
-            "2557 g".to_string(),
-            "62596 h.lst".to_string(),
-            "$ cd e".to_string(),
-            "$ ls".to_string(),
-            "584 i".to_string(),
-            "$ cd ..".to_string(),
-            "$ cd ..".to_string(),
-            "$ cd d".to_string(),
-            "$ ls".to_string(),
-            "4060174 j".to_string(),
-            "8033020 d.log".to_string(),
-            "5626152 d.ext".to_string(),
-            "7214296 k".to_string(),
-        ];
-        assert_eq!(solve_p2(lines), 24933642)
-    }
-}
-
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use regex::Regex;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Path(Vec<String>);
+
+impl Path {
+    fn new() -> Path {
+        Path(vec![])
+    }
+
+    fn root() -> Path {
+        Path(vec![])
+    }
+
+    fn is_empty(&self) -> bool {
+        return self.0.len() == 0
+    }
+
+    fn push(&self, segment: String) -> Path {
+        let mut res = self.clone();
+        res.0.push(segment);
+        res
+    }
+
+    fn pop(&self) -> Path {
+        let mut res = self.clone();
+        res.0.pop();
+        res
+    }
+
+    fn drop(&self, n: usize) -> Path {
+        Path(self.0.iter().skip(n).cloned().collect())
+    }
+
+    fn name(&self) -> Option<&String> {
+        self.0.last()
+    }
+
+    // follows a shell argument like `a/b`, `../c` or `/d` from this path
+    fn resolve(&self, arg: &str) -> Path {
+        let start = if arg.starts_with('/') { Path::root() } else { self.clone() };
+        arg.split('/')
+            .filter(|segment| !segment.is_empty())
+            .fold(start, |path, segment| match segment {
+                "." => path,
+                ".." => path.pop(),
+                name => path.push(name.to_string()),
+            })
+    }
+}
+
+// Implement `Display` for `MinMax`.
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let res = self.0.iter().fold("".to_string(), |mut accum, segment| {
+            accum.push_str("/");
+            accum.push_str(segment);
+            accum
+        });
+
+        match res.as_ref() {
+            "" => write!(f, "/"),
+            path => write!(f, "{}", path)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Entry {
+    File(usize),
+    Dir(Folder),
+}
+
+#[derive(Debug, Clone)]
+struct Folder {
+    name: String,
+
+    files: Vec<(String, usize)>,
+    folders: Vec<Folder>,
+}
+
+impl Folder {
+    fn root() -> Folder {
+        Folder{
+            name: "R".to_string(),
+            files: vec![],
+            folders: vec![]
+        }    
+    }
+
+    fn size(&self) -> usize {
+        self.files.iter().map(|(_, size)| size ).sum::<usize>() + self.folders.iter().map(|f| f.size() ).sum::<usize>()
+    }
+    
+    fn add_file(&self, location: &Path, name: String, size: usize) -> Folder{
+        let mut res = self.clone();
+
+        if location.is_empty() {
+            res.files.push((name, size));
+        } else {
+            res.folders = res.folders.into_iter().map(|folder| {
+                if folder.name == *location.0.first().unwrap() {
+                    folder.add_file(&location.drop(1), name.clone(), size)
+                } else {
+                    folder
+                }
+            }).collect()
+        }
+
+        res
+    }
+
+    fn add_folder(&self, location: &Path, name: String) -> Folder {
+        let mut res = self.clone();
+
+        if location.is_empty() {
+            res.folders.push(Folder{
+                name,
+                files: vec![],
+                folders: vec![]
+            });
+        } else {
+            res.folders = res.folders.into_iter().map(|folder| {
+                if folder.name == *location.0.first().unwrap() {
+                    folder.add_folder(&location.drop(1), name.clone())
+                } else {
+                    folder
+                }
+            }).collect()
+        }
+
+        res
+    }
+
+    fn get(&self, location: &Path) -> Option<&Folder> {
+        location.0.iter().try_fold(self, |folder, segment| {
+            folder.folders.iter().find(|f| f.name == *segment)
+        })
+    }
+
+    fn get_mut(&mut self, location: &Path) -> Option<&mut Folder> {
+        location.0.iter().try_fold(self, |folder, segment| {
+            folder.folders.iter_mut().find(|f| f.name == *segment)
+        })
+    }
+
+    fn file_size(&self, name: &str) -> Option<usize> {
+        self.files.iter().find(|(n, _)| n == name).map(|(_, size)| *size)
+    }
+
+    fn has_entry(&self, name: &str) -> bool {
+        self.file_size(name).is_some() || self.folders.iter().any(|f| f.name == name)
+    }
+
+    // removes and returns whatever lives at `location`
+    fn take(&mut self, location: &Path) -> Option<Entry> {
+        let name = location.name()?;
+        let parent = self.get_mut(&location.pop())?;
+
+        if let Some(i) = parent.files.iter().position(|(n, _)| n == name) {
+            return Some(Entry::File(parent.files.remove(i).1))
+        }
+        let i = parent.folders.iter().position(|f| f.name == *name)?;
+        Some(Entry::Dir(parent.folders.remove(i)))
+    }
+
+    fn put(&mut self, location: &Path, entry: Entry) {
+        let name = location.name().unwrap().clone();
+        let parent = self.get_mut(&location.pop()).unwrap();
+
+        match entry {
+            Entry::File(size) => parent.files.push((name, size)),
+            Entry::Dir(mut folder) => {
+                folder.name = name;
+                parent.folders.push(folder);
+            },
+        }
+    }
+
+    fn all_folders(&self) -> Vec<Folder> {
+        let mut res = vec![self.clone()];
+        for folder in &self.folders {
+            res.append(&mut folder.all_folders())
+        }
+        res
+    }
+
+    fn walk(&self, location: Path) -> Vec<(Path, &Folder)> {
+        let mut res = vec![(location.clone(), self)];
+        for folder in &self.folders {
+            res.append(&mut folder.walk(location.push(folder.name.clone())))
+        }
+        res
+    }
+
+    fn du(&self, location: Path, max_depth: Option<usize>) -> Vec<(Path, usize)> {
+        // children before parents, the way du prints them
+        let mut res = vec![];
+        if max_depth.is_none_or(|d| d > 0) {
+            for folder in &self.folders {
+                res.append(&mut folder.du(location.push(folder.name.clone()), max_depth.map(|d| d - 1)))
+            }
+        }
+        res.push((location, self.size()));
+        res
+    }
+
+    fn find(&self, query: &Query) -> Vec<(Path, Kind, usize)> {
+        let mut res = vec![];
+        for (location, folder) in self.walk(Path::root()) {
+            let name = location.0.last().cloned().unwrap_or("/".to_string());
+            if query.matches(&name, Kind::Dir, folder.size()) {
+                res.push((location.clone(), Kind::Dir, folder.size()));
+            }
+            for (name, size) in &folder.files {
+                if query.matches(name, Kind::File, *size) {
+                    res.push((location.push(name.clone()), Kind::File, *size));
+                }
+            }
+        }
+        res
+    }
+
+    fn tree(&self) -> String {
+        let mut res = format!("- / (dir, size={})\n", self.size());
+        self.tree_entries(1, &mut res);
+        res
+    }
+
+    fn tree_entries(&self, depth: usize, out: &mut String) {
+        let mut entries: Vec<(&String, Option<&Folder>, usize)> = self.folders.iter()
+            .map(|f| (&f.name, Some(f), f.size()))
+            .chain(self.files.iter().map(|(name, size)| (name, None, *size)))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        let indent = "  ".repeat(depth);
+        for (name, folder, size) in entries {
+            match folder {
+                Some(folder) => {
+                    out.push_str(&format!("{}- {} (dir, size={})\n", indent, name, size));
+                    folder.tree_entries(depth + 1, out);
+                },
+                None => out.push_str(&format!("{}- {} (file, size={})\n", indent, name, size)),
+            }
+        }
+    }
+
+    fn smallest_to_free(&self, disk_size: usize, required: usize) -> Option<(Path, usize)> {
+        let unused = disk_size.saturating_sub(self.size());
+        if unused >= required {
+            return None
+        }
+        let needed = required - unused;
+
+        self.walk(Path::root())
+            .into_iter()
+            .map(|(location, folder)| (location, folder.size()))
+            .filter(|(_, size)| *size >= needed)
+            .min_by_key(|(_, size)| *size)
+    }
+
+    fn display(&self, location: Path) {
+        println!("{}, size={}", location, self.size());
+
+        for folder in &self.folders {
+            let new_loc = location.push(folder.name.clone());
+            println!("{}, size={}", new_loc, folder.size());
+            folder.display(new_loc)
+        }
+        for (name, size) in &self.files {
+            println!("{}/{}, size={}", location, name, size)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Dir,
+    File,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kind::Dir => write!(f, "dir"),
+            Kind::File => write!(f, "file"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SizePredicate {
+    Above(usize),
+    Below(usize),
+    Exactly(usize),
+}
+
+impl SizePredicate {
+    fn matches(&self, size: usize) -> bool {
+        match self {
+            SizePredicate::Above(n) => size > *n,
+            SizePredicate::Below(n) => size < *n,
+            SizePredicate::Exactly(n) => size == *n,
+        }
+    }
+}
+
+// same shape as find's -size: +N, -N or N
+impl FromStr for SizePredicate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |n: &str| n.parse::<usize>().map_err(|_| format!("bad size: {:?}", s));
+        match s.chars().next() {
+            Some('+') => Ok(SizePredicate::Above(parse(&s[1..])?)),
+            Some('-') => Ok(SizePredicate::Below(parse(&s[1..])?)),
+            _ => Ok(SizePredicate::Exactly(parse(s)?)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Query {
+    name: Option<String>,
+    sizes: Vec<SizePredicate>,
+    kind: Option<Kind>,
+}
+
+impl Query {
+    fn matches(&self, name: &str, kind: Kind, size: usize) -> bool {
+        self.name.as_ref().is_none_or(|pattern| glob_match(pattern, name))
+            && self.kind.is_none_or(|k| k == kind)
+            && self.sizes.iter().all(|p| p.matches(size))
+    }
+}
+
+// shell style glob supporting `*` and `?`
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = backtrack {
+            p = star_p + 1;
+            n = star_n + 1;
+            backtrack = Some((star_p, star_n + 1));
+        } else {
+            return false
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Problem {
+    DuplicateListing,
+    UnknownDirectory(Path),
+    ConflictingSize { name: String, listed: usize, found: usize },
+    NoSuchEntry(Path),
+    AlreadyExists(Path),
+    MoveIntoItself(Path),
+    UnknownCommand(String),
+    BadArguments(String),
+    Unrecognised(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Diagnostic {
+    line: usize,
+    location: Path,
+    problem: Problem,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} (in {}): ", self.line, self.location)?;
+        match &self.problem {
+            Problem::DuplicateListing => write!(f, "directory listed more than once"),
+            Problem::UnknownDirectory(path) => write!(f, "cd into unlisted directory {}", path),
+            Problem::ConflictingSize { name, listed, found } => {
+                write!(f, "{} was listed with size {}, now {}", name, listed, found)
+            },
+            Problem::NoSuchEntry(path) => write!(f, "no such file or directory {}", path),
+            Problem::AlreadyExists(path) => write!(f, "{} already exists", path),
+            Problem::MoveIntoItself(path) => write!(f, "can't move {} inside itself", path),
+            Problem::UnknownCommand(command) => write!(f, "unknown command {:?}", command),
+            Problem::BadArguments(command) => write!(f, "wrong number of arguments to {:?}", command),
+            Problem::Unrecognised(line) => write!(f, "line didn't match anything: {:?}", line),
+        }
+    }
+}
+
+// replays a shell transcript, collecting problems rather than giving up on them
+fn replay(lines: Vec<String>) -> (Folder, Vec<Diagnostic>) {
+    let command_re = Regex::new(r"^\$ (\S+)\s*(.*)$").unwrap();
+    let disp_dir = Regex::new(r"^dir (.+)$").unwrap();
+    let disp_file = Regex::new(r"^(\d+) (.+)$").unwrap();
+
+    let mut path = Path::new();
+    let mut root = Folder::root();
+    let mut listed = HashSet::<Path>::new();
+    let mut diagnostics = vec![];
+
+    for (i, line) in lines.into_iter().enumerate() {
+        let mut report = |location: &Path, problem: Problem| {
+            diagnostics.push(Diagnostic { line: i + 1, location: location.clone(), problem })
+        };
+
+        if line.is_empty() {
+            continue
+        }
+
+        if let Some(captures) = command_re.captures(&line) {
+            let args: Vec<&str> = captures[2].split_whitespace().collect();
+
+            match (&captures[1], args.as_slice()) {
+                ("cd", [target]) => {
+                    let new_path = path.resolve(target);
+                    if root.get(&new_path).is_none() {
+                        report(&path, Problem::UnknownDirectory(new_path.clone()));
+                        // like mkdir -p, so every directory on the way exists too
+                        for depth in 1..=new_path.0.len() {
+                            let ancestor = Path(new_path.0[..depth].to_vec());
+                            if root.get(&ancestor).is_none() {
+                                root = root.add_folder(&ancestor.pop(), ancestor.name().unwrap().clone());
+                            }
+                        }
+                    }
+                    path = new_path;
+                },
+                ("ls", []) => {
+                    if !listed.insert(path.clone()) {
+                        report(&path, Problem::DuplicateListing);
+                    }
+                },
+                ("mkdir", [target]) => {
+                    let new_path = path.resolve(target);
+                    match (root.get(&new_path.pop()), new_path.name()) {
+                        (Some(parent), Some(name)) if !parent.has_entry(name) => {
+                            root = root.add_folder(&new_path.pop(), name.clone());
+                        },
+                        (Some(_), _) => report(&path, Problem::AlreadyExists(new_path)),
+                        (None, _) => report(&path, Problem::NoSuchEntry(new_path.pop())),
+                    }
+                },
+                ("rm", [target]) => {
+                    let target = path.resolve(target);
+                    if root.take(&target).is_none() {
+                        report(&path, Problem::NoSuchEntry(target));
+                    }
+                },
+                ("mv", [from, to]) => {
+                    let from = path.resolve(from);
+                    let mut to = path.resolve(to);
+                    // moving onto an existing directory moves inside it
+                    if root.get(&to).is_some() {
+                        to = to.push(from.name().cloned().unwrap_or_default());
+                    }
+
+                    let destination_free = match (root.get(&to.pop()), to.name()) {
+                        (Some(parent), Some(name)) => !parent.has_entry(name),
+                        _ => false,
+                    };
+
+                    if !destination_free {
+                        report(&path, Problem::AlreadyExists(to));
+                    } else if to.0.starts_with(&from.0) {
+                        report(&path, Problem::MoveIntoItself(from));
+                    } else {
+                        match root.take(&from) {
+                            Some(entry) => root.put(&to, entry),
+                            None => report(&path, Problem::NoSuchEntry(from)),
+                        }
+                    }
+                },
+                (command @ ("cd" | "ls" | "mkdir" | "rm" | "mv"), _) => {
+                    report(&path, Problem::BadArguments(command.to_string()))
+                },
+                (command, _) => report(&path, Problem::UnknownCommand(command.to_string())),
+            }
+
+        } else if (disp_dir.is_match(&line) || disp_file.is_match(&line)) && root.get(&path).is_none() {
+            // the directory being listed was removed since we moved into it
+            report(&path, Problem::NoSuchEntry(path.clone()));
+
+        } else if let Some(captures) = disp_dir.captures(&line) {
+            let name = &captures[1];
+
+            if !root.get(&path).unwrap().has_entry(name) {
+                root = root.add_folder(&path, name.to_string());
+            }
+
+        } else if let Some(captures) = disp_file.captures(&line) {
+            let size = captures[1].parse::<usize>().unwrap();
+            let name = &captures[2];
+
+            match root.get(&path).unwrap().file_size(name) {
+                None => root = root.add_file(&path, name.to_string(), size),
+                Some(listed) if listed == size => {},
+                Some(listed) => {
+                    report(&path, Problem::ConflictingSize { name: name.to_string(), listed, found: size });
+                    // trust the most recent listing
+                    root.take(&path.push(name.to_string()));
+                    root = root.add_file(&path, name.to_string(), size);
+                },
+            }
+
+        } else {
+            report(&path, Problem::Unrecognised(line.clone()))
+        }
+    }
+
+    (root, diagnostics)
+}
+
+fn build_fs(lines: Vec<String>) -> Folder {
+    replay(lines).0
+}
+
+pub fn solve_p1(lines: Vec<String>) -> u32 {
+    let root = build_fs(lines);
+    root.all_folders()
+        .into_iter()
+        .map(|f| f.size() )
+        .filter(|s| *s <= 100000 )
+        .sum::<usize>() as u32
+}
+
+pub fn solve_p2(lines: Vec<String>) -> u32 {
+    let root = build_fs(lines);
+
+    let disk_space: usize = 70000000;
+    let update = 30000000;
+
+    let (_, size) = root.smallest_to_free(disk_space, update).unwrap();
+    size as u32
+}
+
+fn flag_value(args: &[String], i: usize) -> Result<&String, String> {
+    args.get(i + 1).ok_or(format!("missing value for {}", args[i]))
+}
+
+// query commands over a reconstructed transcript:
+//   check
+//   tree
+//   du [--depth N]
+//   find [--name GLOB] [--size +N|-N|N]... [--type d|f]
+//   free <disk size> <required space>
+pub fn run_query(lines: Vec<String>, args: &[String]) -> Result<String, String> {
+    let (root, diagnostics) = replay(lines);
+    let command = args.first().ok_or("missing command, expected one of check, tree, du, find, free")?;
+
+    match command.as_str() {
+        "check" => Ok(diagnostics.iter().map(|d| format!("{}\n", d)).collect()),
+        "tree" => Ok(root.tree()),
+        "du" => {
+            let mut max_depth = None;
+            let mut i = 1;
+            while i < args.len() {
+                match args[i].as_str() {
+                    "--depth" => {
+                        let value = flag_value(args, i)?;
+                        max_depth = Some(value.parse::<usize>().map_err(|_| format!("bad depth: {:?}", value))?);
+                        i += 2;
+                    },
+                    other => return Err(format!("unknown du argument: {:?}", other)),
+                }
+            }
+
+            Ok(root.du(Path::root(), max_depth)
+                .into_iter()
+                .map(|(location, size)| format!("{}\t{}\n", size, location))
+                .collect())
+        },
+        "find" => {
+            let mut query = Query::default();
+            let mut i = 1;
+            while i < args.len() {
+                let value = flag_value(args, i)?;
+                match args[i].as_str() {
+                    "--name" => query.name = Some(value.clone()),
+                    "--size" => query.sizes.push(value.parse::<SizePredicate>()?),
+                    "--type" => query.kind = match value.as_str() {
+                        "d" => Some(Kind::Dir),
+                        "f" => Some(Kind::File),
+                        other => return Err(format!("bad type: {:?}, expected d or f", other)),
+                    },
+                    other => return Err(format!("unknown find argument: {:?}", other)),
+                }
+                i += 2;
+            }
+
+            Ok(root.find(&query)
+                .into_iter()
+                .map(|(location, kind, size)| format!("{}\t{}\t{}\n", location, kind, size))
+                .collect())
+        },
+        "free" => {
+            let parse = |i: usize| -> Result<usize, String> {
+                let value = args.get(i).ok_or("usage: free <disk size> <required space>")?;
+                value.parse::<usize>().map_err(|_| format!("bad number: {:?}", value))
+            };
+            let (disk_size, required) = (parse(1)?, parse(2)?);
+
+            match root.smallest_to_free(disk_size, required) {
+                Some((location, size)) => Ok(format!("{}\t{}\n", size, location)),
+                None => Ok("nothing to delete, enough space is already free\n".to_string()),
+            }
+        },
+        other => Err(format!("unknown command: {:?}", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::day7::day7::{build_fs, glob_match, replay, run_query, solve_p1, solve_p2, Kind, Path, Problem, Query, SizePredicate};
+
+    fn example() -> Vec<String> {
+        vec![
+            "$ cd /".to_string(),
+            "$ ls".to_string(),
+            "dir a".to_string(),
+            "14848514 b.txt".to_string(),
+            "8504156 c.dat".to_string(),
+            "dir d".to_string(),
+            "$ cd a".to_string(),
+            "$ ls".to_string(),
+            "dir e".to_string(),
+            "29116 f".to_string(),
+            "2557 g".to_string(),
+            "62596 h.lst".to_string(),
+            "$ cd e".to_string(),
+            "$ ls".to_string(),
+            "584 i".to_string(),
+            "$ cd ..".to_string(),
+            "$ cd ..".to_string(),
+            "$ cd d".to_string(),
+            "$ ls".to_string(),
+            "4060174 j".to_string(),
+            "8033020 d.log".to_string(),
+            "5626152 d.ext".to_string(),
+            "7214296 k".to_string(),
+        ]
+    }
+
+    #[test]
+    fn test_solve_p1() {
+        assert_eq!(solve_p1(example()), 95437)
+    }
+
+    #[test]
+    fn test_solve_p2() {
+        assert_eq!(solve_p2(example()), 24933642)
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.txt", "b.txt"));
+        assert!(glob_match("d.*", "d.log"));
+        assert!(glob_match("?", "k"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("*.txt", "c.dat"));
+        assert!(!glob_match("?", "ab"));
+    }
+
+    #[test]
+    fn test_du() {
+        let root = build_fs(example());
+        let du: Vec<(String, usize)> = root.du(Path::root(), Some(1))
+            .into_iter()
+            .map(|(location, size)| (location.to_string(), size))
+            .collect();
+        assert_eq!(du, vec![
+            ("/a".to_string(), 94853),
+            ("/d".to_string(), 24933642),
+            ("/".to_string(), 48381165),
+        ]);
+    }
+
+    #[test]
+    fn test_find() {
+        let root = build_fs(example());
+        let query = Query {
+            name: Some("*.*".to_string()),
+            sizes: vec![SizePredicate::Above(100000), SizePredicate::Below(10000000)],
+            kind: Some(Kind::File),
+        };
+        let found: Vec<String> = root.find(&query)
+            .into_iter()
+            .map(|(location, _, _)| location.to_string())
+            .collect();
+        assert_eq!(found, vec!["/c.dat", "/d/d.log", "/d/d.ext"]);
+
+        assert_eq!("+100".parse::<SizePredicate>(), Ok(SizePredicate::Above(100)));
+        assert_eq!("-100".parse::<SizePredicate>(), Ok(SizePredicate::Below(100)));
+        assert_eq!("100".parse::<SizePredicate>(), Ok(SizePredicate::Exactly(100)));
+    }
+
+    #[test]
+    fn test_tree() {
+        let root = build_fs(example());
+        assert_eq!(root.tree(), vec![
+            "- / (dir, size=48381165)",
+            "  - a (dir, size=94853)",
+            "    - e (dir, size=584)",
+            "      - i (file, size=584)",
+            "    - f (file, size=29116)",
+            "    - g (file, size=2557)",
+            "    - h.lst (file, size=62596)",
+            "  - b.txt (file, size=14848514)",
+            "  - c.dat (file, size=8504156)",
+            "  - d (dir, size=24933642)",
+            "    - d.ext (file, size=5626152)",
+            "    - d.log (file, size=8033020)",
+            "    - j (file, size=4060174)",
+            "    - k (file, size=7214296)",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_smallest_to_free() {
+        let root = build_fs(example());
+        let (location, size) = root.smallest_to_free(70000000, 30000000).unwrap();
+        assert_eq!((location.to_string(), size), ("/d".to_string(), 24933642));
+
+        let (location, size) = root.smallest_to_free(48381165, 500).unwrap();
+        assert_eq!((location.to_string(), size), ("/a/e".to_string(), 584));
+
+        assert!(root.smallest_to_free(100000000, 30000000).is_none());
+    }
+
+    #[test]
+    fn test_run_query() {
+        let args = |s: &str| s.split(' ').map(|a| a.to_string()).collect::<Vec<String>>();
+
+        assert_eq!(run_query(example(), &args("du --depth 0")), Ok("48381165\t/\n".to_string()));
+        assert_eq!(run_query(example(), &args("find --name e")), Ok("/a/e\tdir\t584\n".to_string()));
+        assert_eq!(run_query(example(), &args("free 70000000 30000000")), Ok("24933642\t/d\n".to_string()));
+        assert!(run_query(example(), &args("find --size")).is_err());
+        assert!(run_query(example(), &args("nope")).is_err());
+    }
+
+    #[test]
+    fn test_replay_clean() {
+        let (root, diagnostics) = replay(example());
+        assert_eq!(diagnostics, vec![]);
+        assert_eq!(root.size(), 48381165);
+    }
+
+    #[test]
+    fn test_replay_diagnostics() {
+        let lines = example().into_iter()
+            .chain(vec![
+                "$ cd /".to_string(),
+                "$ ls".to_string(),
+                "14848514 b.txt".to_string(),
+                "100 c.dat".to_string(),
+                "$ cd x".to_string(),
+                "$ cd".to_string(),
+                "$ cd a b".to_string(),
+                "$ ls x".to_string(),
+                "$ pwd".to_string(),
+                "hello".to_string(),
+            ])
+            .collect();
+        let (root, diagnostics) = replay(lines);
+        let problems: Vec<(usize, Problem)> = diagnostics.into_iter().map(|d| (d.line, d.problem)).collect();
+
+        assert_eq!(problems, vec![
+            (25, Problem::DuplicateListing),
+            (27, Problem::ConflictingSize { name: "c.dat".to_string(), listed: 8504156, found: 100 }),
+            (28, Problem::UnknownDirectory(Path::root().push("x".to_string()))),
+            (29, Problem::BadArguments("cd".to_string())),
+            (30, Problem::BadArguments("cd".to_string())),
+            (31, Problem::BadArguments("ls".to_string())),
+            (32, Problem::UnknownCommand("pwd".to_string())),
+            (33, Problem::Unrecognised("hello".to_string())),
+        ]);
+        // the repeated listing isn't counted twice, the newer size wins
+        assert_eq!(root.size(), 48381165 - 8504156 + 100);
+    }
+
+    #[test]
+    fn test_replay_edits() {
+        let lines = example().into_iter()
+            .chain(vec![
+                "$ cd /".to_string(),
+                "$ rm b.txt".to_string(),
+                "$ mkdir z".to_string(),
+                "$ mv d/k z".to_string(),
+                "$ mv a/e/i a/e/renamed".to_string(),
+                "$ mv a d".to_string(),
+                "$ rm nope".to_string(),
+                "$ mkdir z".to_string(),
+            ])
+            .collect();
+        let (root, diagnostics) = replay(lines);
+        let problems: Vec<Problem> = diagnostics.into_iter().map(|d| d.problem).collect();
+
+        assert_eq!(problems, vec![
+            Problem::NoSuchEntry(Path::root().push("nope".to_string())),
+            Problem::AlreadyExists(Path::root().push("z".to_string())),
+        ]);
+        assert_eq!(root.size(), 48381165 - 14848514);
+        assert_eq!(root.tree(), vec![
+            "- / (dir, size=33532651)",
+            "  - c.dat (file, size=8504156)",
+            "  - d (dir, size=17814199)",
+            "    - a (dir, size=94853)",
+            "      - e (dir, size=584)",
+            "        - renamed (file, size=584)",
+            "      - f (file, size=29116)",
+            "      - g (file, size=2557)",
+            "      - h.lst (file, size=62596)",
+            "    - d.ext (file, size=5626152)",
+            "    - d.log (file, size=8033020)",
+            "    - j (file, size=4060174)",
+            "  - z (dir, size=7214296)",
+            "    - k (file, size=7214296)",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_replay_cd_into_missing_ancestors() {
+        let lines = vec![
+            "$ cd /".to_string(),
+            "$ cd x/y".to_string(),
+            "$ ls".to_string(),
+            "dir z".to_string(),
+            "5 f".to_string(),
+        ];
+        let (root, diagnostics) = replay(lines);
+        let y = Path::root().push("x".to_string()).push("y".to_string());
+
+        assert_eq!(diagnostics.into_iter().map(|d| d.problem).collect::<Vec<Problem>>(), vec![
+            Problem::UnknownDirectory(y.clone()),
+        ]);
+        assert!(root.get(&y.push("z".to_string())).is_some());
+        assert_eq!(root.size(), 5);
+    }
+
+    #[test]
+    fn test_replay_listing_removed_directory() {
+        let lines = vec![
+            "$ cd /".to_string(),
+            "$ ls".to_string(),
+            "dir a".to_string(),
+            "$ cd a".to_string(),
+            "$ rm /a".to_string(),
+            "$ ls".to_string(),
+            "10 f".to_string(),
+            "dir b".to_string(),
+        ];
+        let (root, diagnostics) = replay(lines);
+        let a = Path::root().push("a".to_string());
+        let problems: Vec<(usize, Problem)> = diagnostics.into_iter().map(|d| (d.line, d.problem)).collect();
+
+        assert_eq!(problems, vec![
+            (7, Problem::NoSuchEntry(a.clone())),
+            (8, Problem::NoSuchEntry(a.clone())),
+        ]);
+        assert!(root.get(&a).is_none());
+        assert_eq!(root.size(), 0);
+    }
+}
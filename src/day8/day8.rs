@@ -1,197 +1,444 @@
-
-fn as_grid(lines: Vec<String>) -> Vec<Vec<i32>> {
-    lines.into_iter()
-        .map(|l| {
-            l.chars()
-                .map(|c| c.to_digit(10).unwrap() as i32 )
-                .collect::<Vec<i32>>()
-        })
-        .collect()
-}
-
-pub fn solve_p1(lines: Vec<String>) -> u32 {
-    // mark all as invisible
-    let mut grid: Vec<Vec<(i32, bool)>> = as_grid(lines).into_iter().map(|v| {
-        v.into_iter().map(|h| (h, false)).collect()
-    }).collect();
-
-    let x = grid.len();
-    let y = grid[0].len();
-
-    for i in 0..x {
-        // coming from bottom
-        let mut highest: i32 = -1;
-        for j in 0..y {
-            if grid[i][j].0 > highest {
-                highest = grid[i][j].0;
-                grid[i][j].1 = true;
-            }
-        }
-
-        // coming from top
-        let mut highest: i32 = -1;
-        for j in 0..y {
-            if grid[i][y - j - 1].0 > highest {
-                highest = grid[i][y - j - 1].0;
-                grid[i][y - j - 1].1 = true;
-            }
-        }
-    }
-
-    for j in 0..y {
-        // coming from left
-        let mut highest: i32 = -1;
-        for i in 0..x {
-            if grid[i][j].0 > highest {
-                highest = grid[i][j].0;
-                grid[i][j].1 = true;
-            }
-        }
-
-        // coming from right
-        let mut highest: i32 = -1;
-        for i in 0..x {
-            if grid[x - i - 1][j].0 > highest {
-                highest = grid[x - i - 1][j].0;
-                grid[x - i - 1][j].1 = true;
-            }
-        }
-    }
-
-    grid.iter().map(|v| {
-        v.iter().map(|(_, visible)| {
-            if *visible {
-                1
-            } else {
-                0
-            }
-        }).sum::<u32>()
-    }).sum()
-}
-
-fn scenic_score_at(grid: &Vec<Vec<i32>>, i: usize, j: usize) -> i32 {
-    let x = grid.len();
-    let y = grid[0].len();
-
-    let mut scores: Vec<i32> = vec![];
-    let t = grid[i][j];
-
-    // looking right
-    let mut k = 0;
-    loop {
-        k += 1;
-        if i + k >= x {
-            k -= 1;
-            break
-        }
-        if grid[i + k][j] >= t {
-            break
-        }
-    }
-    scores.push(k as i32);
-
-    // looking left
-    let mut k = 0;
-    loop {
-        k += 1;
-        if k > i {
-            k -= 1;
-            break
-        }
-        if grid[i - k][j] >= t {
-            break
-        }
-    }
-    scores.push(k as i32);
-
-    // looking up
-    let mut k = 0;
-    loop {
-        k += 1;
-        if j + k >= y {
-            k -= 1;
-            break
-        }
-        if grid[i][j + k] >= t {
-            break
-        }
-    }
-    scores.push(k as i32);
-
-    // looking down
-    let mut k = 0;
-    loop {
-        k += 1;
-        if k > j {
-            k -= 1;
-            break
-        }
-        if grid[i][j - k] >= t {
-            break
-        }
-    }
-    scores.push(k as i32);
-
-
-    scores.into_iter().reduce(|acc, v| acc * v ).unwrap()
-}
-
-pub fn solve_p2(lines: Vec<String>) -> u32 {
-    let grid = as_grid(lines);
-
-    let mut highest = 0;
-    for (i, v) in grid.clone().into_iter().enumerate() {
-        for (j, _) in v.into_iter().enumerate() {
-            let ss = scenic_score_at(&grid, i, j);
-            if ss > highest {
-                highest = ss;
-            }
-        }
-    }
-
-    highest as u32
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::day8::day8::{as_grid, scenic_score_at, solve_p1, solve_p2};
-
-    #[test]
-    fn test_scenic_score_at() {
-        let lines: Vec<String> = vec![
-            "30373".to_owned(),
-            "25512".to_owned(),
-            "65332".to_owned(),
-            "33549".to_owned(),
-            "35390".to_owned(),
-        ];
-        let grid = as_grid(lines);
-        assert_eq!(scenic_score_at(&grid, 1, 2), 4);
-        assert_eq!(scenic_score_at(&grid, 3, 2), 8);
-    }
-
-    #[test]
-    fn test_solve_p1() {
-        let lines: Vec<String> = vec![
-            "30373".to_owned(),
-            "25512".to_owned(),
-            "65332".to_owned(),
-            "33549".to_owned(),
-            "35390".to_owned(),
-        ];
-        assert_eq!(solve_p1(lines), 21)
-    }
-
-    #[test]
-    fn test_solve_p2() {
-        let lines: Vec<String> = vec![
-            "30373".to_owned(),
-            "25512".to_owned(),
-            "65332".to_owned(),
-            "33549".to_owned(),
-            "35390".to_owned(),
-        ];
-        assert_eq!(solve_p2(lines), 8)
-    }
-}
-
+
+fn as_grid(lines: Vec<String>) -> Vec<Vec<i32>> {
+    lines.into_iter()
+        .map(|l| {
+            l.chars()
+                .map(|c| c.to_digit(10).unwrap() as i32 )
+                .collect::<Vec<i32>>()
+        })
+        .collect()
+}
+
+// walks a line of trees with a monotonic stack of indices whose heights never increase.
+// for each tree gives how far it can see back towards the start of the line, and whether
+// it can be seen from that end
+fn scan_line(heights: &[i32]) -> Vec<(usize, bool)> {
+    let mut stack: Vec<usize> = vec![];
+
+    heights.iter()
+        .enumerate()
+        .map(|(i, h)| {
+            while stack.last().is_some_and(|top| heights[*top] < *h) {
+                stack.pop();
+            }
+            let res = match stack.last() {
+                Some(top) => (i - top, false),
+                None => (i, true),
+            };
+            stack.push(i);
+            res
+        })
+        .collect()
+}
+
+const CARDINAL: [(i64, i64); 4] = [(0, 1), (0, -1), (1, 0), (-1, 0)];
+const DIAGONAL: [(i64, i64); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+
+fn directions(diagonals: bool) -> Vec<(i64, i64)> {
+    let mut res = CARDINAL.to_vec();
+    if diagonals {
+        res.extend(DIAGONAL);
+    }
+    res
+}
+
+fn step(grid: &[Vec<i32>], (i, j): (usize, usize), (di, dj): (i64, i64)) -> Option<(usize, usize)> {
+    let i = usize::try_from(i as i64 + di).ok()?;
+    let j = usize::try_from(j as i64 + dj).ok()?;
+    grid.get(i)?.get(j)?;
+    Some((i, j))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combine {
+    Product,
+    Sum,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ScoringRule {
+    combine: Combine,
+    diagonals: bool,
+}
+
+impl ScoringRule {
+    fn puzzle() -> ScoringRule {
+        ScoringRule { combine: Combine::Product, diagonals: false }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Survey {
+    visible: Vec<Vec<bool>>,
+    scores: Vec<Vec<u64>>,
+}
+
+// every row of the forest has to be as long as the first
+fn check_rectangular(grid: &[Vec<i32>]) -> Result<(), String> {
+    let width = grid.first().map_or(0, |row| row.len());
+    match grid.iter().position(|row| row.len() != width) {
+        Some(i) => Err(format!("row {} has {} trees, expected {}", i, grid[i].len(), width)),
+        None => Ok(()),
+    }
+}
+
+impl Survey {
+    fn new(grid: &[Vec<i32>]) -> Result<Survey, String> {
+        Survey::with_rule(grid, &ScoringRule::puzzle())
+    }
+
+    fn with_rule(grid: &[Vec<i32>], rule: &ScoringRule) -> Result<Survey, String> {
+        check_rectangular(grid)?;
+        let x = grid.len();
+        let y = grid.first().map_or(0, |row| row.len());
+
+        let identity = match rule.combine {
+            Combine::Product => 1,
+            Combine::Sum => 0,
+        };
+        let mut visible = vec![vec![false; y]; x];
+        let mut scores = vec![vec![identity; y]; x];
+
+        for direction in directions(rule.diagonals) {
+            let back = (-direction.0, -direction.1);
+
+            // every line starts on the edge the trees are looking towards
+            let starts = (0..x)
+                .flat_map(|i| (0..y).map(move |j| (i, j)))
+                .filter(|cell| step(grid, *cell, direction).is_none());
+
+            for start in starts {
+                let cells: Vec<(usize, usize)> = std::iter::successors(Some(start), |cell| step(grid, *cell, back)).collect();
+                let heights: Vec<i32> = cells.iter().map(|(i, j)| grid[*i][*j]).collect();
+
+                for ((i, j), (distance, seen)) in cells.into_iter().zip(scan_line(&heights)) {
+                    visible[i][j] |= seen;
+                    scores[i][j] = match rule.combine {
+                        Combine::Product => scores[i][j] * distance as u64,
+                        Combine::Sum => scores[i][j] + distance as u64,
+                    };
+                }
+            }
+        }
+
+        Ok(Survey { visible, scores })
+    }
+
+    // best scoring trees first, ties broken by position
+    fn top_k(&self, k: usize) -> Vec<((usize, usize), u64)> {
+        let mut res: Vec<((usize, usize), u64)> = self.scores.iter()
+            .enumerate()
+            .flat_map(|(i, row)| row.iter().enumerate().map(move |(j, s)| ((i, j), *s)))
+            .collect();
+        res.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        res.truncate(k);
+        res
+    }
+
+    fn visible_count(&self) -> usize {
+        self.visible.iter().flatten().filter(|v| **v).count()
+    }
+
+    fn best_score(&self) -> u64 {
+        self.scores.iter().flatten().max().cloned().unwrap_or(0)
+    }
+
+    // one row per line of the forest, `#` for trees visible from outside
+    fn visibility_map(&self) -> String {
+        self.visible.iter()
+            .map(|row| row.iter().map(|v| if *v { '#' } else { '.' }).collect::<String>() + "\n")
+            .collect()
+    }
+
+    fn scores_csv(&self) -> String {
+        self.scores.iter()
+            .map(|row| row.iter().map(|s| s.to_string()).collect::<Vec<String>>().join(",") + "\n")
+            .collect()
+    }
+}
+
+// trees a viewer standing on top of `from` can see, looking straight out along each
+// direction until the first tree at least as tall (which is itself seen)
+fn visible_from(grid: &[Vec<i32>], from: (usize, usize), diagonals: bool) -> Vec<(usize, usize)> {
+    let height = grid[from.0][from.1];
+    let mut res = vec![];
+
+    for direction in directions(diagonals) {
+        let mut cell = from;
+        while let Some(next) = step(grid, cell, direction) {
+            res.push(next);
+            if grid[next.0][next.1] >= height {
+                break
+            }
+            cell = next;
+        }
+    }
+
+    res.sort();
+    res
+}
+
+// whether the straight line between the tops of two trees clears every tree in between.
+// cells along the way are sampled one per step of the longer axis
+fn line_of_sight(grid: &[Vec<i32>], from: (usize, usize), to: (usize, usize)) -> bool {
+    let (di, dj) = (to.0 as i64 - from.0 as i64, to.1 as i64 - from.1 as i64);
+    let steps = di.abs().max(dj.abs());
+    let (h_from, h_to) = (grid[from.0][from.1] as i64, grid[to.0][to.1] as i64);
+
+    (1..steps).all(|k| {
+        // nearest cell to the point k/steps of the way along, rounding halves away from `from`
+        let round = |d: i64| (2 * d * k + d.signum() * steps).div_euclid(2 * steps);
+        let (i, j) = ((from.0 as i64 + round(di)) as usize, (from.1 as i64 + round(dj)) as usize);
+
+        // the sight line is at height h_from + (h_to - h_from) * k / steps above this cell
+        (grid[i][j] as i64) * steps < h_from * steps + (h_to - h_from) * k
+    })
+}
+
+fn parse_cell(args: &[String], i: usize) -> Result<(usize, usize), String> {
+    let parse = |n: Option<&String>| -> Result<usize, String> {
+        let n = n.ok_or("expected a row and a column")?;
+        n.parse::<usize>().map_err(|_| format!("bad coordinate: {:?}", n))
+    };
+    Ok((parse(args.get(i))?, parse(args.get(i + 1))?))
+}
+
+// inspection output for a forest:
+//   visibility
+//   scores [--sum] [--diagonal]
+//   top <k> [--sum] [--diagonal]
+//   from <row> <col> [--diagonal]
+//   sight <row> <col> <row> <col>
+pub fn run_query(lines: Vec<String>, args: &[String]) -> Result<String, String> {
+    let lines = lines.into_iter().filter(|l| !l.is_empty()).collect();
+    let grid = as_grid(lines);
+    check_rectangular(&grid)?;
+
+    let diagonals = args.iter().any(|a| a == "--diagonal");
+    let rule = ScoringRule {
+        combine: if args.iter().any(|a| a == "--sum") { Combine::Sum } else { Combine::Product },
+        diagonals,
+    };
+    let in_bounds = |(i, j): (usize, usize)| -> Result<(usize, usize), String> {
+        match grid.get(i).and_then(|row| row.get(j)) {
+            Some(_) => Ok((i, j)),
+            None => Err(format!("{},{} is outside the forest", i, j)),
+        }
+    };
+
+    match args.first().map(|a| a.as_str()) {
+        Some("visibility") => Ok(Survey::new(&grid)?.visibility_map()),
+        Some("scores") => Ok(Survey::with_rule(&grid, &rule)?.scores_csv()),
+        Some("top") => {
+            let k = args.get(1)
+                .and_then(|k| k.parse::<usize>().ok())
+                .ok_or("expected the number of trees to list")?;
+            Ok(Survey::with_rule(&grid, &rule)?.top_k(k)
+                .into_iter()
+                .map(|((i, j), score)| format!("{},{}\t{}\n", i, j, score))
+                .collect())
+        },
+        Some("from") => {
+            let from = in_bounds(parse_cell(args, 1)?)?;
+            Ok(visible_from(&grid, from, diagonals)
+                .into_iter()
+                .map(|(i, j)| format!("{},{}\n", i, j))
+                .collect())
+        },
+        Some("sight") => {
+            let from = in_bounds(parse_cell(args, 1)?)?;
+            let to = in_bounds(parse_cell(args, 3)?)?;
+            Ok(format!("{}\n", line_of_sight(&grid, from, to)))
+        },
+        _ => Err("expected one of visibility, scores, top, from, sight".to_string()),
+    }
+}
+
+pub fn solve_p1(lines: Vec<String>) -> u32 {
+    Survey::new(&as_grid(lines)).unwrap().visible_count() as u32
+}
+
+// direct outward scan from a single tree, kept to check `Survey` against
+#[cfg(test)]
+fn scenic_score_at(grid: &[Vec<i32>], i: usize, j: usize) -> i32 {
+    let x = grid.len();
+    let y = grid[0].len();
+
+    let mut scores: Vec<i32> = vec![];
+    let t = grid[i][j];
+
+    // looking right
+    let mut k = 0;
+    loop {
+        k += 1;
+        if i + k >= x {
+            k -= 1;
+            break
+        }
+        if grid[i + k][j] >= t {
+            break
+        }
+    }
+    scores.push(k as i32);
+
+    // looking left
+    let mut k = 0;
+    loop {
+        k += 1;
+        if k > i {
+            k -= 1;
+            break
+        }
+        if grid[i - k][j] >= t {
+            break
+        }
+    }
+    scores.push(k as i32);
+
+    // looking up
+    let mut k = 0;
+    loop {
+        k += 1;
+        if j + k >= y {
+            k -= 1;
+            break
+        }
+        if grid[i][j + k] >= t {
+            break
+        }
+    }
+    scores.push(k as i32);
+
+    // looking down
+    let mut k = 0;
+    loop {
+        k += 1;
+        if k > j {
+            k -= 1;
+            break
+        }
+        if grid[i][j - k] >= t {
+            break
+        }
+    }
+    scores.push(k as i32);
+
+
+    scores.into_iter().reduce(|acc, v| acc * v ).unwrap()
+}
+
+pub fn solve_p2(lines: Vec<String>) -> u32 {
+    Survey::new(&as_grid(lines)).unwrap().best_score() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lcg::Lcg;
+    use crate::day8::day8::{as_grid, line_of_sight, run_query, scenic_score_at, solve_p1, solve_p2, visible_from, Combine, ScoringRule, Survey};
+
+    fn example() -> Vec<String> {
+        vec![
+            "30373".to_owned(),
+            "25512".to_owned(),
+            "65332".to_owned(),
+            "33549".to_owned(),
+            "35390".to_owned(),
+        ]
+    }
+
+    #[test]
+    fn test_scenic_score_at() {
+        let grid = as_grid(example());
+        assert_eq!(scenic_score_at(&grid, 1, 2), 4);
+        assert_eq!(scenic_score_at(&grid, 3, 2), 8);
+    }
+
+    #[test]
+    fn test_solve_p1() {
+        assert_eq!(solve_p1(example()), 21)
+    }
+
+    #[test]
+    fn test_solve_p2() {
+        assert_eq!(solve_p2(example()), 8)
+    }
+
+    #[test]
+    fn test_survey_maps() {
+        let survey = Survey::new(&as_grid(example())).unwrap();
+        assert_eq!(survey.visibility_map(), "#####\n###.#\n##.##\n#.#.#\n#####\n");
+        assert_eq!(survey.scores_csv().lines().nth(3), Some("0,1,8,3,0"));
+    }
+
+    #[test]
+    fn test_survey_matches_direct_scan() {
+        // pseudo random forest, deterministic so failures reproduce
+        let mut rng = Lcg::new(2022);
+        let grid: Vec<Vec<i32>> = (0..60).map(|_| {
+            (0..80).map(|_| rng.below(10) as i32).collect()
+        }).collect();
+
+        let survey = Survey::new(&grid).unwrap();
+        for i in 0..grid.len() {
+            for j in 0..grid[0].len() {
+                assert_eq!(survey.scores[i][j], scenic_score_at(&grid, i, j) as u64, "at {} {}", i, j);
+            }
+        }
+    }
+
+    #[test]
+    fn test_top_k() {
+        let survey = Survey::new(&as_grid(example())).unwrap();
+        assert_eq!(survey.top_k(3), vec![((3, 2), 8), ((2, 1), 6), ((1, 2), 4)]);
+    }
+
+    #[test]
+    fn test_scoring_rules() {
+        let grid = as_grid(example());
+
+        let sum = Survey::with_rule(&grid, &ScoringRule { combine: Combine::Sum, diagonals: false }).unwrap();
+        // (3, 2) sees 2 up, 2 left, 1 down and 2 right
+        assert_eq!(sum.scores[3][2], 7);
+        assert_eq!(sum.scores[0][0], 4);
+
+        let diagonal = Survey::with_rule(&grid, &ScoringRule { combine: Combine::Sum, diagonals: true }).unwrap();
+        // diagonally (3, 2) sees (2, 1), (2, 3), (4, 1), (4, 3) and stops at the 5 on (1, 0)
+        assert_eq!(diagonal.scores[3][2], 7 + 2 + 1 + 1 + 1);
+        // three way tie, broken by position
+        assert_eq!(diagonal.top_k(3), vec![((0, 3), 12), ((2, 0), 12), ((3, 2), 12)]);
+    }
+
+    #[test]
+    fn test_visible_from() {
+        let grid = as_grid(example());
+        assert_eq!(visible_from(&grid, (3, 2), false), vec![(1, 2), (2, 2), (3, 0), (3, 1), (3, 3), (3, 4), (4, 2)]);
+        assert_eq!(visible_from(&grid, (0, 0), true), vec![(0, 1), (0, 2), (1, 0), (1, 1), (2, 0)]);
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        let grid = as_grid(example());
+        // neighbours always see each other
+        assert!(line_of_sight(&grid, (0, 0), (0, 1)));
+        // the 5 at (1, 1) blocks (0, 0) from the 3 at (2, 2)
+        assert!(!line_of_sight(&grid, (0, 0), (2, 2)));
+        // the 9 at (3, 4) looks down the column over the 3 and 2 to the 7
+        assert!(line_of_sight(&grid, (3, 4), (0, 3)));
+        assert!(line_of_sight(&grid, (3, 4), (0, 4)));
+        // the 7 at (0, 3) looks down over the 5 at (1, 2) to (4, 1)
+        assert!(line_of_sight(&grid, (0, 3), (4, 1)));
+        // the 5 at (3, 2) hides the 3 at (0, 2) from the 3 at (4, 2)
+        assert!(!line_of_sight(&grid, (4, 2), (0, 2)));
+    }
+
+    #[test]
+    fn test_ragged_forest() {
+        let mut grid = as_grid(example());
+        grid[2].pop();
+        assert_eq!(Survey::new(&grid).unwrap_err(), "row 2 has 4 trees, expected 5");
+
+        let lines = vec!["303".to_owned(), "2551".to_owned()];
+        assert!(run_query(lines.clone(), &["visibility".to_string()]).is_err());
+        assert!(run_query(lines, &["sight".to_string(), "0".to_string(), "0".to_string(), "1".to_string(), "3".to_string()]).is_err());
+    }
+}
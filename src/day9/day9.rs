@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::iter;
 
@@ -8,6 +8,52 @@ enum Direction {
     Left,
     Up,
     Down,
+    UpRight,
+    UpLeft,
+    DownRight,
+    DownLeft,
+}
+
+impl Direction {
+    fn delta(&self) -> (i32, i32) {
+        match self {
+            Direction::Right => (1, 0),
+            Direction::Left => (-1, 0),
+            Direction::Up => (0, 1),
+            Direction::Down => (0, -1),
+            Direction::UpRight => (1, 1),
+            Direction::UpLeft => (-1, 1),
+            Direction::DownRight => (1, -1),
+            Direction::DownLeft => (-1, -1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Metric {
+    // diagonal neighbours count as touching, the puzzle's rule
+    Chebyshev,
+    // only orthogonal neighbours count as touching
+    Manhattan,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FollowRule {
+    metric: Metric,
+    slack: i32,
+}
+
+impl FollowRule {
+    fn puzzle() -> FollowRule {
+        FollowRule { metric: Metric::Chebyshev, slack: 1 }
+    }
+
+    fn distance(&self, dx: i32, dy: i32) -> i32 {
+        match self.metric {
+            Metric::Chebyshev => dx.abs().max(dy.abs()),
+            Metric::Manhattan => dx.abs() + dy.abs(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -18,64 +64,33 @@ struct Pos {
 
 impl Pos {
     fn do_move(&mut self, dir: Direction) {
-        match dir {
-            Direction::Right => {
-                self.x += 1;
-            },
-            Direction::Left => {
-                self.x -= 1;
-            },
-            Direction::Up => {
-                self.y += 1;
-            },
-            Direction::Down => {
-                self.y -= 1;
-            },
-        }
+        let (dx, dy) = dir.delta();
+        self.x += dx;
+        self.y += dy;
     }
 
-    fn follow(&mut self, other: &Pos) {
-        match other.x - self.x {
-            2 => {
-                self.x += 1;
-                match other.y - self.y {
-                    2 | 1 => self.y += 1,
-                    -2 | -1 => self.y -= 1,
-                    _ => {}
-                }
-                // self.y = other.y;
+    fn follow(&mut self, other: &Pos, rule: &FollowRule) {
+        loop {
+            let (dx, dy) = (other.x - self.x, other.y - self.y);
+            if rule.distance(dx, dy) <= rule.slack {
+                break
             }
-            -2 => {
-                self.x -= 1;
-                match other.y - self.y {
-                    2 | 1 => self.y += 1,
-                    -2 | -1 => self.y -= 1,
-                    _ => {}
-                }
-                // self.y = other.y;
-            }
-            _ => {}
-        }
-        match other.y - self.y {
-            2 => {
-                self.y += 1;
-                match other.x - self.x {
-                    2 | 1 => self.x += 1,
-                    -2 | -1 => self.x -= 1,
-                    _ => {}
-                }
-                // self.x = other.x;
-            }
-            -2 => {
-                self.y -= 1;
-                match other.x - self.x {
-                    2 | 1 => self.x += 1,
-                    -2 | -1 => self.x -= 1,
-                    _ => {}
-                }
-                // self.x = other.x;
+
+            match rule.metric {
+                // one step straight or diagonally towards the other knot
+                Metric::Chebyshev => {
+                    self.x += dx.signum();
+                    self.y += dy.signum();
+                },
+                // single orthogonal steps along the wider gap until close enough
+                Metric::Manhattan => {
+                    if dx.abs() >= dy.abs() {
+                        self.x += dx.signum();
+                    } else {
+                        self.y += dy.signum();
+                    }
+                },
             }
-            _ => {}
         }
     }
 }
@@ -89,63 +104,125 @@ impl Display for Pos {
 struct Snake {
     body: Vec<Pos>,
     size: usize,
+    rule: FollowRule,
 }
 
 impl Snake {
-    fn new(length: usize) -> Snake {
+    fn new(length: usize, rule: FollowRule) -> Snake {
         Snake{
-            body: iter::repeat(Pos{x: 0, y: 0}).take(length).collect(),
+            body: iter::repeat_n(Pos{x: 0, y: 0}, length).collect(),
             size: length,
+            rule,
         }
     }
 
-    fn do_move(&mut self, dir: Direction) -> Pos {
+    fn do_move(&mut self, dir: Direction) {
         self.body[0].do_move(dir);
 
         for i in 1..self.size {
             let prev = self.body[i - 1].clone();
-            self.body[i].follow(&prev);
+            self.body[i].follow(&prev, &self.rule);
         }
+    }
+}
+
+// inclusive box around a set of positions, drawn with y going up
+struct Bounds {
+    min: Pos,
+    max: Pos,
+}
+
+impl Bounds {
+    fn around<'a>(positions: impl Iterator<Item = &'a Pos>) -> Bounds {
+        let origin = Pos { x: 0, y: 0 };
+        positions.fold(Bounds { min: origin.clone(), max: origin }, |b, p| Bounds {
+            min: Pos { x: b.min.x.min(p.x), y: b.min.y.min(p.y) },
+            max: Pos { x: b.max.x.max(p.x), y: b.max.y.max(p.y) },
+        })
+    }
 
-        // self.show(dir);
-        self.body[self.size - 1].clone()
+    fn tiles<T: Clone>(&self, fill: T) -> Vec<Vec<T>> {
+        let width = (self.max.x - self.min.x + 1) as usize;
+        let height = (self.max.y - self.min.y + 1) as usize;
+        vec![vec![fill; width]; height]
     }
 
-    fn show(&self, dir: Direction) {
-        let m = 11;
-        let n = 2;
+    fn index(&self, p: &Pos) -> (usize, usize) {
+        ((self.max.y - p.y) as usize, (p.x - self.min.x) as usize)
+    }
+}
+
+// every knot's position at the start and after each single step of the head
+struct Trajectories {
+    knots: Vec<Vec<Pos>>,
+}
+
+impl Trajectories {
+    fn visits(&self, knot: usize) -> HashMap<Pos, usize> {
+        let mut res = HashMap::new();
+        for p in &self.knots[knot] {
+            *res.entry(p.clone()).or_insert(0) += 1;
+        }
+        res
+    }
 
-        let mut tiles: Vec<Vec<String>> = iter::repeat(
-            iter::repeat(".".to_owned()).take(m).collect()
-        ).take(m).collect();
+    fn distinct(&self, knot: usize) -> usize {
+        self.visits(knot).len()
+    }
 
-        println!("{:?}: {}", dir, self.body[0]);
-        for (i, p) in self.body.iter().enumerate() {
-            let s = if i == 0 {
-                "H".to_owned()
-            } else {
-                i.to_string()
+    // visit counts drawn over every cell any knot reached, `.` for never and `*` past 9
+    fn heatmap(&self, knot: usize) -> String {
+        let bounds = Bounds::around(self.knots.iter().flatten());
+        let mut tiles = bounds.tiles('.');
+        for (p, count) in self.visits(knot) {
+            let (row, col) = bounds.index(&p);
+            tiles[row][col] = match count {
+                1..=9 => char::from_digit(count as u32, 10).unwrap(),
+                _ => '*',
             };
-            tiles[(m - 1) - (p.y + n) as usize][(p.x + n) as usize] = s;
         }
 
-        tiles.into_iter().for_each(|v| {
-            println!("{}", String::from_iter(v))
-        });
-        println!("\n\n");
+        tiles.into_iter()
+            .map(|row| String::from_iter(row) + "\n")
+            .collect()
+    }
+
+    fn visits_csv(&self, knot: usize) -> String {
+        let mut visits: Vec<(Pos, usize)> = self.visits(knot).into_iter().collect();
+        visits.sort_by_key(|(p, _)| (p.y, p.x));
+
+        iter::once("x,y,visits\n".to_owned())
+            .chain(visits.into_iter().map(|(p, count)| format!("{},{},{}\n", p.x, p.y, count)))
+            .collect()
+    }
+
+    fn trajectory_csv(&self, knot: usize) -> String {
+        iter::once("step,x,y\n".to_owned())
+            .chain(self.knots[knot].iter().enumerate().map(|(i, p)| format!("{},{},{}\n", i, p.x, p.y)))
+            .collect()
     }
 }
 
-fn solve(lines: Vec<String>, snake_len: usize) -> u32 {
-    let mut s = Snake::new(snake_len);
-    to_directions(lines)
-        .into_iter()
+fn simulate(moves: Vec<(Direction, usize)>, snake_len: usize, rule: FollowRule) -> Trajectories {
+    let mut s = Snake::new(snake_len, rule);
+    let mut knots: Vec<Vec<Pos>> = s.body.iter().map(|p| vec![p.clone()]).collect();
+
+    moves.into_iter()
         .flat_map(|(dir, n)| {
-            iter::repeat(dir).take(n)
+            iter::repeat_n(dir, n)
         })
-        .map(|dir| s.do_move(dir) )
-        .collect::<HashSet<Pos>>()
-        .len() as u32
+        .for_each(|dir| {
+            s.do_move(dir);
+            for (trajectory, p) in knots.iter_mut().zip(s.body.iter()) {
+                trajectory.push(p.clone());
+            }
+        });
+
+    Trajectories { knots }
+}
+
+fn solve(lines: Vec<String>, snake_len: usize) -> u32 {
+    simulate(to_directions(lines), snake_len, FollowRule::puzzle()).distinct(snake_len - 1) as u32
 }
 
 pub fn solve_p1(lines: Vec<String>) -> u32 {
@@ -157,6 +234,46 @@ pub fn solve_p2(lines: Vec<String>) -> u32 {
     solve(lines, 10)
 }
 
+// rope queries, all taking the number of knots and which knot to report (0 is the head):
+//   visits <knots> <knot> [--manhattan] [--slack N]
+//   heatmap <knots> <knot> [--manhattan] [--slack N]
+//   trajectory <knots> <knot> [--manhattan] [--slack N]
+pub fn run_query(lines: Vec<String>, args: &[String]) -> Result<String, String> {
+    let lines = lines.into_iter().filter(|l| !l.is_empty()).collect();
+
+    let number = |i: usize, what: &str| -> Result<usize, String> {
+        let arg = args.get(i).ok_or(format!("missing {}", what))?;
+        arg.parse::<usize>().map_err(|_| format!("bad {}: {:?}", what, arg))
+    };
+    let knots = number(1, "knot count")?;
+    let knot = number(2, "knot index")?;
+    if knot >= knots {
+        return Err(format!("knot {} doesn't exist on a rope of {}", knot, knots))
+    }
+
+    let mut rule = FollowRule::puzzle();
+    let mut i = 3;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--manhattan" => rule.metric = Metric::Manhattan,
+            "--slack" => {
+                i += 1;
+                rule.slack = number(i, "slack")? as i32;
+            },
+            other => return Err(format!("unknown argument: {:?}", other)),
+        }
+        i += 1;
+    }
+
+    let trajectories = simulate(to_directions(lines), knots, rule);
+    match args[0].as_str() {
+        "visits" => Ok(trajectories.visits_csv(knot)),
+        "heatmap" => Ok(trajectories.heatmap(knot)),
+        "trajectory" => Ok(trajectories.trajectory_csv(knot)),
+        other => Err(format!("unknown command: {:?}", other)),
+    }
+}
+
 fn to_directions(lines: Vec<String>) -> Vec<(Direction, usize)> {
     lines.into_iter().map(|l| {
         let parts: Vec<String> = l.split(" ").map(|p| p.to_owned()).collect();
@@ -165,6 +282,10 @@ fn to_directions(lines: Vec<String>) -> Vec<(Direction, usize)> {
             "L" => Some(Direction::Left),
             "U" => Some(Direction::Up),
             "D" => Some(Direction::Down),
+            "UR" => Some(Direction::UpRight),
+            "UL" => Some(Direction::UpLeft),
+            "DR" => Some(Direction::DownRight),
+            "DL" => Some(Direction::DownLeft),
             _ => None
         }.unwrap();
 
@@ -174,11 +295,10 @@ fn to_directions(lines: Vec<String>) -> Vec<(Direction, usize)> {
 
 #[cfg(test)]
 mod tests {
-    use crate::day9::day9::{solve_p1, solve_p2};
+    use crate::day9::day9::{simulate, solve_p1, solve_p2, to_directions, FollowRule, Metric, Pos};
 
-    #[test]
-    fn test_solve_p1() {
-        let lines: Vec<String> = vec![
+    fn example() -> Vec<String> {
+        vec![
             "R 4".to_owned(),
             "U 4".to_owned(),
             "L 3".to_owned(),
@@ -187,23 +307,17 @@ mod tests {
             "D 1".to_owned(),
             "L 5".to_owned(),
             "R 2".to_owned(),
-        ];
-        assert_eq!(solve_p1(lines), 13)
+        ]
+    }
+
+    #[test]
+    fn test_solve_p1() {
+        assert_eq!(solve_p1(example()), 13)
     }
 
     #[test]
     fn test_solve_p2() {
-        let lines: Vec<String> = vec![
-            "R 4".to_owned(),
-            "U 4".to_owned(),
-            "L 3".to_owned(),
-            "D 1".to_owned(),
-            "R 4".to_owned(),
-            "D 1".to_owned(),
-            "L 5".to_owned(),
-            "R 2".to_owned(),
-        ];
-        assert_eq!(solve_p2(lines), 1)
+        assert_eq!(solve_p2(example()), 1)
     }
 
     #[test]
@@ -220,5 +334,52 @@ mod tests {
         ];
         assert_eq!(solve_p2(lines), 36)
     }
-}
 
+    #[test]
+    fn test_trajectories() {
+        let trajectories = simulate(to_directions(example()), 2, FollowRule::puzzle());
+        // start plus one entry per step
+        assert_eq!(trajectories.knots[0].len(), 25);
+        assert_eq!(trajectories.knots[0].last(), Some(&Pos { x: 2, y: 2 }));
+        assert_eq!(trajectories.knots[1].last(), Some(&Pos { x: 1, y: 2 }));
+        assert_eq!(trajectories.distinct(0), 21);
+        assert_eq!(trajectories.distinct(1), 13);
+        assert_eq!(trajectories.visits(1).get(&Pos { x: 4, y: 3 }), Some(&6));
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let trajectories = simulate(to_directions(example()), 2, FollowRule::puzzle());
+        assert_eq!(trajectories.heatmap(1), [
+            "..41..",
+            "...16.",
+            ".3111.",
+            "....1.",
+            "2112..",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn test_diagonal_moves() {
+        let lines = vec!["UR 3".to_owned(), "DL 1".to_owned()];
+        let trajectories = simulate(to_directions(lines), 3, FollowRule::puzzle());
+        assert_eq!(trajectories.knots[0].last(), Some(&Pos { x: 2, y: 2 }));
+        assert_eq!(trajectories.knots[1].last(), Some(&Pos { x: 2, y: 2 }));
+        assert_eq!(trajectories.knots[2].last(), Some(&Pos { x: 1, y: 1 }));
+    }
+
+    #[test]
+    fn test_follow_rules() {
+        let lines = vec!["R 1".to_owned(), "U 1".to_owned(), "R 1".to_owned()];
+
+        let chebyshev = simulate(to_directions(lines.clone()), 2, FollowRule::puzzle());
+        assert_eq!(chebyshev.knots[1], vec![Pos { x: 0, y: 0 }, Pos { x: 0, y: 0 }, Pos { x: 0, y: 0 }, Pos { x: 1, y: 1 }]);
+
+        let manhattan = simulate(to_directions(lines.clone()), 2, FollowRule { metric: Metric::Manhattan, slack: 1 });
+        assert_eq!(manhattan.knots[1], vec![Pos { x: 0, y: 0 }, Pos { x: 0, y: 0 }, Pos { x: 1, y: 0 }, Pos { x: 2, y: 0 }]);
+
+        let loose = simulate(to_directions(lines), 2, FollowRule { metric: Metric::Chebyshev, slack: 2 });
+        assert_eq!(loose.distinct(1), 1);
+    }
+}
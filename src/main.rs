@@ -1,20 +1,88 @@
-use std::fs;
+use std::{env, fs, process};
+mod day7;
+mod day8;
+mod day9;
+mod day10;
+mod day11;
+mod day12;
+mod day13;
+mod day14;
+mod day15;
+mod day16;
+// still in progress, and doesn't build yet
+#[cfg(any())]
 mod day22;
+#[cfg(test)]
+mod lcg;
 
+#[cfg(any())]
 use crate::day22::day22::{solve_p1, solve_p2};
 
-fn get_lines(day: u32) -> Vec<String>  {
-    let file_contents = fs::read_to_string(format!("src/day{day}/input.txt")).expect("Failed to read file");
+fn read_lines(path: &str) -> Vec<String> {
+    let file_contents = fs::read_to_string(path).expect("Failed to read file");
     return file_contents
         .split("\n")
         .map(|l| l.trim_end().to_string() )
         .collect()
 }
 
+fn get_lines(day: &str) -> Vec<String>  {
+    read_lines(&format!("src/{day}/input.txt"))
+}
 
-fn main() {
-    let lines = get_lines(22);
-    println!("part 1: {}", solve_p1(lines.clone())); // 189016 => too high
-    println!("part 2: {}", solve_p2(lines));
+// both parts of a day's puzzle, against its own input
+fn solve(day: &str) -> (String, String) {
+    let lines = get_lines(day);
+    match day {
+        "day7" => (day7::day7::solve_p1(lines.clone()).to_string(), day7::day7::solve_p2(lines).to_string()),
+        "day8" => (day8::day8::solve_p1(lines.clone()).to_string(), day8::day8::solve_p2(lines).to_string()),
+        "day9" => (day9::day9::solve_p1(lines.clone()).to_string(), day9::day9::solve_p2(lines).to_string()),
+        "day10" => (day10::day10::solve_p1(lines.clone()).to_string(), day10::day10::solve_p2(lines)),
+        "day11" => (day11::day11::solve_p1(lines.clone()).to_string(), day11::day11::solve_p2(lines).to_string()),
+        "day12" => (day12::day12::solve_p1(lines.clone()).to_string(), day12::day12::solve_p2(lines).to_string()),
+        "day13" => (day13::day13::solve_p1(lines.clone()).to_string(), day13::day13::solve_p2(lines).to_string()),
+        "day14" => (day14::day14::solve_p1(lines.clone()).to_string(), day14::day14::solve_p2(lines).to_string()),
+        "day15" => (day15::day15::solve_p1(lines.clone()).to_string(), day15::day15::solve_p2(lines).to_string()),
+        "day16" => (day16::day16::solve_p1(lines.clone()).to_string(), day16::day16::solve_p2(lines).to_string()),
+        other => panic!("no solution for {:?}", other),
+    }
 }
 
+fn main() {
+    // `<day> <input file> <query...>` runs one of the day's queries against any input,
+    // and `<day>` alone solves that day
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() >= 2 {
+        let run_query = match args[0].as_str() {
+            "day7" => day7::day7::run_query,
+            "day8" => day8::day8::run_query,
+            "day9" => day9::day9::run_query,
+            "day10" => day10::day10::run_query,
+            "day11" => day11::day11::run_query,
+            "day12" => day12::day12::run_query,
+            "day13" => day13::day13::run_query,
+            "day14" => day14::day14::run_query,
+            "day15" => day15::day15::run_query,
+            "day16" => day16::day16::run_query,
+            other => panic!("no queries for {:?}", other),
+        };
+        match run_query(read_lines(&args[1]), &args[2..]) {
+            Ok(output) => print!("{}", output),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
+    } else if let Some(day) = args.first() {
+        let (part1, part2) = solve(day);
+        println!("part 1: {}", part1);
+        println!("part 2: {}", part2);
+    } else {
+        #[cfg(any())]
+        {
+            let lines = get_lines("day22");
+            println!("part 1: {}", solve_p1(lines.clone())); // 189016 => too high
+            println!("part 2: {}", solve_p2(lines));
+        }
+    }
+}